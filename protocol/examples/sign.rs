@@ -0,0 +1,50 @@
+//! Sign a firmware image for OTA updates.
+//!
+//! `cargo run --example sign -- keygen` prints a new signing key and its public key.
+//! `cargo run --example sign -- <image>` reads the hex encoded signing key from the
+//! `OTA_SIGNING_KEY` environment variable and writes `<image>.sha256` and `<image>.sig`.
+
+use std::io::Read;
+use std::path::Path;
+
+use esp_pulser_protocol::*;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn main() {
+    let Some(arg) = std::env::args().nth(1) else {
+        eprintln!("Usage: sign keygen | sign <image>");
+        std::process::exit(1);
+    };
+
+    if arg == "keygen" {
+        let mut key = [0; 32];
+        std::fs::File::open("/dev/urandom")
+            .and_then(|mut f| f.read_exact(&mut key))
+            .expect("cannot read /dev/urandom");
+        let public_key = ed25519_dalek::SigningKey::from_bytes(&key).verifying_key();
+        println!("signing key (keep secret): {}", hex(&key));
+        println!(
+            "public key (keys/ota_signing.pub): {}",
+            hex(public_key.as_bytes())
+        );
+        return;
+    }
+
+    let key = std::env::var("OTA_SIGNING_KEY").expect("OTA_SIGNING_KEY is not set");
+    let key = decode_hex(key.trim()).expect("OTA_SIGNING_KEY must be 32 hex encoded bytes");
+
+    let image = std::fs::read(&arg).expect("cannot read image");
+    let digest = image_digest(&image);
+    let file_name = Path::new(&arg).file_name().unwrap().to_string_lossy();
+
+    std::fs::write(
+        format!("{arg}.sha256"),
+        format_image_digest(&digest, &file_name),
+    )
+    .unwrap();
+    std::fs::write(format!("{arg}.sig"), sign_image_digest(&key, &digest)).unwrap();
+    println!("Signed {arg}");
+}
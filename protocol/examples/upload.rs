@@ -0,0 +1,92 @@
+//! Push a firmware image to a device over the command connection.
+//!
+//! Usage: `cargo run --example upload -- [--force] <device ip> <image> [pairing secret as hex]`
+//!
+//! The image must be the trimmed OTA binary (`espflash save-image`), not the ELF file,
+//! with its signature next to it in `<image>.sig`, as produced by the `sign` example.
+//! `--force` installs the image even if it is not newer than the running firmware.
+
+use std::io::Read;
+use std::net::TcpStream;
+
+use esp_pulser_protocol::*;
+
+fn request(stream: &mut TcpStream, id: u32, command: Command) -> Result<Reply, CommandError> {
+    write_frame(stream, &encode(&Request { id, command }).unwrap()).unwrap();
+
+    let mut pending = Vec::new();
+    let mut buf = [0; 1024];
+    loop {
+        if let Frame::Complete { payload, .. } = parse_frame(&pending) {
+            let response: Response = decode(payload).expect("malformed response");
+            assert_eq!(response.id, id, "response to another request");
+            return response.result;
+        }
+        let n = stream.read(&mut buf).expect("connection lost");
+        assert!(n > 0, "connection closed by device");
+        pending.extend_from_slice(&buf[..n]);
+    }
+}
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().collect();
+    let force = args.iter().any(|arg| arg == "--force");
+    args.retain(|arg| arg != "--force");
+    if args.len() < 3 {
+        eprintln!(
+            "Usage: {} [--force] <device ip> <image> [pairing secret as hex]",
+            args[0]
+        );
+        std::process::exit(1);
+    }
+
+    let image = std::fs::read(&args[2]).expect("cannot read image");
+    let signature = std::fs::read(format!("{}.sig", args[2])).expect("cannot read signature");
+    let mut stream = TcpStream::connect((args[1].as_str(), 12345)).expect("cannot connect");
+    let mut id = 0;
+    let mut next_id = || {
+        id += 1;
+        id
+    };
+
+    println!("{:?}", request(&mut stream, next_id(), Command::Hello));
+
+    if let Some(hex) = args.get(3) {
+        let secret: [u8; PAIRING_SECRET_LEN] = decode_hex(hex).expect("invalid secret");
+        let Ok(Reply::Challenge(challenge)) =
+            request(&mut stream, next_id(), Command::AuthChallenge)
+        else {
+            panic!("no challenge received");
+        };
+        let response = auth_response(&secret, &challenge);
+        request(&mut stream, next_id(), Command::Authenticate(response))
+            .expect("authentication failed");
+    }
+
+    let begin = Command::UploadBegin {
+        size: image.len() as u32,
+        sha256: image_digest(&image),
+        signature,
+        force,
+    };
+    request(&mut stream, next_id(), begin).expect("upload refused");
+
+    for (i, chunk) in image.chunks(UPLOAD_CHUNK_LEN).enumerate() {
+        let chunk = Command::UploadChunk {
+            offset: (i * UPLOAD_CHUNK_LEN) as u32,
+            data: chunk.to_vec(),
+        };
+        match request(&mut stream, next_id(), chunk) {
+            Ok(Reply::UploadProgress { written, total }) => {
+                print!("\r{written}/{total} bytes");
+            }
+            other => panic!("\nupload failed: {other:?}"),
+        }
+    }
+    println!();
+
+    match request(&mut stream, next_id(), Command::UploadFinish) {
+        Ok(_) => println!("Upload complete, the device is restarting"),
+        Err(e) => eprintln!("Upload failed: {e}"),
+    }
+}
@@ -0,0 +1,33 @@
+//! Challenge-response authentication of command clients.
+//!
+//! The device and its paired clients share a secret. To authenticate, a client asks
+//! for a random challenge and answers with `HMAC-SHA256(secret, challenge)`.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+pub const PAIRING_SECRET_LEN: usize = 32;
+pub const CHALLENGE_LEN: usize = 32;
+
+pub type PairingSecret = [u8; PAIRING_SECRET_LEN];
+pub type Challenge = [u8; CHALLENGE_LEN];
+pub type AuthResponse = [u8; 32];
+
+fn mac(secret: &PairingSecret, challenge: &Challenge) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(challenge);
+    mac
+}
+
+pub fn auth_response(secret: &PairingSecret, challenge: &Challenge) -> AuthResponse {
+    mac(secret, challenge).finalize().into_bytes().into()
+}
+
+/// Check a client's answer to a challenge in constant time.
+pub fn verify_auth_response(
+    secret: &PairingSecret,
+    challenge: &Challenge,
+    response: &AuthResponse,
+) -> bool {
+    mac(secret, challenge).verify_slice(response).is_ok()
+}
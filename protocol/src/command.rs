@@ -0,0 +1,356 @@
+//! Framed command protocol spoken over the TCP command connection.
+//!
+//! Every frame starts with [`FRAME_MAGIC`], followed by the protocol version byte and
+//! the payload length as a little endian `u32`. The payload is a bincode encoded
+//! [`Request`] (client to device) or [`Response`] (device to client). Legacy GUI builds
+//! send a bare command byte (0-4) instead, which never collides with the magic byte.
+
+use std::io::{self, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::auth::*;
+use crate::event_log::*;
+use crate::image::*;
+use crate::info::*;
+use crate::packet::*;
+use crate::recording::*;
+use crate::settings::*;
+use crate::subscription::*;
+
+pub const PROTOCOL_VERSION: u8 = 1;
+pub const FRAME_MAGIC: u8 = 0xA5;
+pub const FRAME_HEADER_LEN: usize = 6;
+pub const MAX_FRAME_LEN: usize = 4096;
+
+/// Largest firmware chunk that fits into a single frame
+pub const UPLOAD_CHUNK_LEN: usize = 3072;
+/// Most recording bytes returned by one [`Command::ReadRecording`]
+pub const RECORDING_CHUNK_LEN: usize = 3072;
+/// Most core dump bytes returned by one [`Command::ReadCoreDump`]
+pub const CORE_DUMP_CHUNK_LEN: usize = 3072;
+
+pub const MAX_CLIENTS: usize = 4;
+/// Framed clients that send nothing for this long are disconnected
+pub const CLIENT_IDLE_TIMEOUT_SECS: u64 = 120;
+/// Legacy clients cannot send keep-alives, so they get much longer before they are
+/// disconnected
+pub const LEGACY_IDLE_TIMEOUT_SECS: u64 = 30 * 60;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Request {
+    pub id: u32,
+    pub command: Command,
+}
+
+/// Variants are encoded by their position, so new ones must only ever be appended.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Command {
+    /// Report firmware and protocol version
+    Hello,
+    Restart,
+    /// Download and flash firmware from a URL or a release tag. The update runs in the
+    /// background and reports its progress as [`Packet::Update`] on the status stream.
+    /// Images that are not newer than the running firmware are refused.
+    Update(String),
+    SetLedAmplitude(u8),
+    /// Stream UDP packets to the given port on the requesting host
+    ConnectUdp(u16),
+    SetHapticAmplitude(u8),
+    GetConfig,
+    GetDetectorParams,
+    GetNetworkInfo,
+    GetPartitions,
+    GetSensorHealth,
+    Subscribe(Subscription),
+    /// Stop streaming to the given port on the requesting host
+    Unsubscribe(u16),
+    ListSubscribers,
+    ListClients,
+    /// Request a challenge to authenticate with
+    AuthChallenge,
+    /// Answer the last challenge, see [`auth_response`]
+    Authenticate(AuthResponse),
+    /// Set a new pairing secret. Allowed without authentication only while unpaired.
+    Pair(PairingSecret),
+    /// Start pushing a firmware image of `size` bytes over this connection, together
+    /// with its digest and signature, see [`sign_image_digest`]. Until the upload
+    /// finishes, only the other `Upload*` commands are accepted. With `force` set,
+    /// older or identical versions are installed as well.
+    UploadBegin {
+        size: u32,
+        sha256: ImageDigest,
+        signature: Vec<u8>,
+        force: bool,
+    },
+    /// Each chunk is acknowledged with [`Reply::UploadProgress`] once written
+    UploadChunk {
+        offset: u32,
+        data: Vec<u8>,
+    },
+    /// Verify the image and boot into it
+    UploadFinish,
+    UploadAbort,
+    /// Report how the last firmware update went
+    GetUpdateOutcome,
+    /// Stop the running firmware update, leaving the current image in place
+    CancelUpdate,
+    GetUpdateStatus,
+    /// Like [`Command::Update`], but also installs older or identical versions
+    ForceUpdate(String),
+    /// Fetch the release manifest and compare it with the running firmware
+    CheckForUpdate,
+    /// Set the base URL the release manifest is fetched from, or reset it to the
+    /// default with an empty string
+    SetReleaseUrl(String),
+    GetBootInfo,
+    /// Boot the firmware in the other OTA slot, restarting right away
+    SwitchBootSlot,
+    ExportSettings,
+    /// Replace all settings, which are validated, stored and applied right away
+    ImportSettings(Settings),
+    /// Erase all settings and stored state, including the pairing secret, and restart
+    FactoryReset,
+    /// Start recording samples, beats and events to a new file on the device
+    StartRecording,
+    StopRecording,
+    ListRecordings,
+    /// Read up to [`RECORDING_CHUNK_LEN`] bytes of a recording, starting at `offset`
+    ReadRecording {
+        name: String,
+        offset: u32,
+    },
+    DeleteRecording(String),
+    /// Send the recent history of the data stream to the subscriber at the given port
+    /// on the requesting host, before its live stream resumes. Answered once the
+    /// history has been sent.
+    ReplayHistory(u16),
+    GetCrashReport,
+    /// Read up to [`CORE_DUMP_CHUNK_LEN`] bytes of the stored core dump, starting at the
+    /// given offset
+    ReadCoreDump(u32),
+    /// Erase the stored core dump and the last panic message
+    EraseCoreDump,
+    /// Read up to [`EVENT_LOG_PAGE_LEN`] event log entries, starting at the given
+    /// sequence number
+    GetEventLog(u32),
+    ClearEventLog,
+}
+
+impl Command {
+    /// Privileged commands require an authenticated connection once the device is
+    /// paired. Everything else only reads state or subscribes to the data stream. The
+    /// core dump is privileged, as the memory it holds can include secrets.
+    pub fn is_privileged(&self) -> bool {
+        matches!(
+            self,
+            Command::Restart
+                | Command::Update(_)
+                | Command::ForceUpdate(_)
+                | Command::SetReleaseUrl(_)
+                | Command::SwitchBootSlot
+                | Command::ImportSettings(_)
+                | Command::FactoryReset
+                | Command::StartRecording
+                | Command::StopRecording
+                | Command::DeleteRecording(_)
+                | Command::ReadCoreDump(_)
+                | Command::EraseCoreDump
+                | Command::ClearEventLog
+                | Command::SetLedAmplitude(_)
+                | Command::SetHapticAmplitude(_)
+                | Command::Pair(_)
+                | Command::UploadBegin { .. }
+                | Command::CancelUpdate
+        )
+    }
+
+    /// Refuse a privileged command from a client that did not authenticate, once the
+    /// device is paired. `paired` is only checked when it matters.
+    pub fn authorize(
+        &self,
+        authenticated: bool,
+        paired: impl FnOnce() -> Result<bool, CommandError>,
+    ) -> Result<(), CommandError> {
+        if self.is_privileged() && !authenticated && paired()? {
+            return Err(CommandError::Unauthorized);
+        }
+        Ok(())
+    }
+
+    /// Whether the device restarts once it answered the command with `result`. Only a
+    /// command that succeeded restarts it, a refused or failed one never does.
+    pub fn restarts_after(&self, result: &Result<Reply, CommandError>) -> bool {
+        result.is_ok()
+            && matches!(
+                self,
+                Command::Restart | Command::SwitchBootSlot | Command::FactoryReset
+            )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    pub id: u32,
+    pub result: Result<Reply, CommandError>,
+}
+
+/// Variants are encoded by their position, so new ones must only ever be appended.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Reply {
+    Ok,
+    Hello {
+        firmware_version: [u8; 3],
+        protocol_version: u8,
+    },
+    Config(DeviceConfig),
+    DetectorParams(DetectorParams),
+    NetworkInfo(NetworkInfo),
+    Partitions(PartitionsInfo),
+    SensorHealth(SensorHealth),
+    Subscribers(Vec<SubscriberInfo>),
+    Clients(Vec<ClientInfo>),
+    Challenge(Challenge),
+    UploadProgress {
+        written: u32,
+        total: u32,
+    },
+    /// `None` if no update has been installed since the outcome was last recorded
+    UpdateOutcome(Option<UpdateOutcome>),
+    UpdateStatus(UpdateStatus),
+    UpdateCheck(UpdateCheck),
+    BootInfo(BootInfo),
+    Settings(Settings),
+    /// The recording that was started or stopped
+    Recording(RecordingInfo),
+    Recordings(RecordingList),
+    /// Empty once `offset` reaches the end of the recording
+    RecordingChunk {
+        offset: u32,
+        total: u32,
+        data: Vec<u8>,
+    },
+    CrashReport(CrashReport),
+    /// Empty once `offset` reaches the end of the core dump
+    CoreDumpChunk {
+        offset: u32,
+        total: u32,
+        data: Vec<u8>,
+    },
+    /// Empty once there are no entries past the requested sequence number
+    EventLog(Vec<LogEntry>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, thiserror::Error)]
+pub enum CommandError {
+    #[error("unsupported protocol version {0}")]
+    UnsupportedVersion(u8),
+    #[error("malformed request")]
+    Malformed,
+    #[error("frame of {0} bytes exceeds the limit")]
+    FrameTooLarge(u32),
+    #[error("storage error: {0}")]
+    Storage(String),
+    #[error("network error: {0}")]
+    Network(String),
+    #[error("update failed: {0}")]
+    Update(String),
+    #[error("too many stream subscribers")]
+    TooManySubscribers,
+    #[error("not subscribed")]
+    NotSubscribed,
+    #[error("too many command clients")]
+    TooManyClients,
+    #[error("command requires authentication")]
+    Unauthorized,
+    #[error("authentication failed")]
+    AuthFailed,
+    #[error("device is not paired")]
+    NotPaired,
+    #[error("no firmware upload in progress")]
+    NoUpload,
+    #[error("firmware upload in progress")]
+    UploadInProgress,
+    #[error("firmware update in progress")]
+    UpdateInProgress,
+    #[error("no firmware update in progress")]
+    NoUpdate,
+    #[error("invalid settings: {0}")]
+    InvalidSettings(String),
+    #[error("no recording in progress")]
+    NoRecording,
+    #[error("recording in progress")]
+    RecordingInProgress,
+    #[error("recording {0} not found")]
+    RecordingNotFound(String),
+    #[error("history replay in progress")]
+    ReplayInProgress,
+    #[error("no core dump stored")]
+    NoCoreDump,
+}
+
+/// Result of looking for a complete frame at the start of a receive buffer.
+#[derive(Debug)]
+pub enum Frame<'a> {
+    /// More bytes are needed before the frame can be decoded
+    Incomplete,
+    /// The buffer starts with a legacy single byte command
+    Legacy,
+    Complete {
+        version: u8,
+        payload: &'a [u8],
+        len: usize,
+    },
+    TooLarge(u32),
+}
+
+pub fn parse_frame(buf: &[u8]) -> Frame<'_> {
+    match buf.first() {
+        None => return Frame::Incomplete,
+        Some(&FRAME_MAGIC) => (),
+        Some(_) => return Frame::Legacy,
+    }
+    if buf.len() < FRAME_HEADER_LEN {
+        return Frame::Incomplete;
+    }
+
+    let payload_len = u32::from_le_bytes([buf[2], buf[3], buf[4], buf[5]]);
+    if payload_len as usize > MAX_FRAME_LEN {
+        return Frame::TooLarge(payload_len);
+    }
+
+    let len = FRAME_HEADER_LEN + payload_len as usize;
+    if buf.len() < len {
+        return Frame::Incomplete;
+    }
+
+    Frame::Complete {
+        version: buf[1],
+        payload: &buf[FRAME_HEADER_LEN..len],
+        len,
+    }
+}
+
+pub fn write_frame(writer: &mut impl Write, payload: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+    frame.push(FRAME_MAGIC);
+    frame.push(PROTOCOL_VERSION);
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(payload);
+    writer.write_all(&frame)
+}
+
+/// Translate a legacy single byte command, as sent by older GUI builds.
+pub fn parse_legacy(buf: &[u8]) -> Option<Command> {
+    let arg = |i: usize| buf.get(i).copied();
+    match buf.first()? {
+        0 => Some(Command::Restart),
+        1 => String::from_utf8(buf[1..].to_vec())
+            .ok()
+            .map(Command::Update),
+        2 => Some(Command::SetLedAmplitude(arg(1)?)),
+        3 => Some(Command::ConnectUdp(u16::from_be_bytes([arg(1)?, arg(2)?]))),
+        4 => Some(Command::SetHapticAmplitude(arg(1)?)),
+        _ => None,
+    }
+}
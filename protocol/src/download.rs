@@ -0,0 +1,246 @@
+//! Resumable HTTP downloads, used for firmware images and their signature files.
+//!
+//! [`download`] is independent of the HTTP stack: the device drives it with the ESP-IDF
+//! client, while the tests use a plain TCP client against a local server. A dropped
+//! connection or timeout is retried with exponential backoff, resuming with a `Range`
+//! request from the last byte handed to the [`DownloadSink`].
+
+use std::thread;
+use std::time::Duration;
+
+/// Tuning of [`download`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct DownloadConfig {
+    /// Bytes read from the connection at a time
+    pub chunk_len: usize,
+    /// Connect and read timeout, applied by the [`HttpClient`]
+    pub timeout: Duration,
+    /// Attempts in a row that make no progress before giving up
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        Self {
+            chunk_len: 4096,
+            timeout: Duration::from_secs(10),
+            max_attempts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(16),
+        }
+    }
+}
+
+impl DownloadConfig {
+    /// Delay before the given retry, counting from 1, doubling up to `max_backoff`.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 1u32
+            .checked_shl(retry.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// Why a download failed. Only [transient](DownloadError::is_transient) errors are
+/// retried.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum DownloadError {
+    #[error("connection failed: {0}")]
+    Connect(String),
+    #[error("timed out")]
+    Timeout,
+    #[error("connection interrupted: {0}")]
+    Interrupted(String),
+    #[error("HTTP status {0}")]
+    Status(u16),
+    #[error("response has no content length")]
+    UnknownSize,
+    #[error("server does not support resuming")]
+    RangeNotSupported,
+    #[error("server sent an unexpected range")]
+    InvalidRange,
+    #[error("file size changed from {expected} to {actual} bytes")]
+    SizeChanged { expected: usize, actual: usize },
+    /// The sink refused the data
+    #[error("{0}")]
+    Rejected(String),
+    #[error("cancelled")]
+    Cancelled,
+    #[error("gave up after {attempts} attempts: {last}")]
+    RetriesExhausted {
+        attempts: u32,
+        last: Box<DownloadError>,
+    },
+}
+
+impl DownloadError {
+    /// Errors that may go away by trying again: network trouble and server errors.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            DownloadError::Connect(_) | DownloadError::Timeout | DownloadError::Interrupted(_) => {
+                true
+            }
+            DownloadError::Status(status) => matches!(status, 408 | 429 | 500..=599),
+            _ => false,
+        }
+    }
+}
+
+/// Status line and headers of a response, as far as [`download`] cares.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResponseHead {
+    pub status: u16,
+    pub content_length: Option<usize>,
+    /// Value of the `Content-Range` header
+    pub content_range: Option<String>,
+}
+
+/// Minimal HTTP client interface needed by [`download`].
+pub trait HttpClient {
+    /// Send a `GET` request, with a `Range: bytes=<offset>-` header if `offset` is not
+    /// zero, and return the response head. Any previous response is discarded.
+    fn get(&mut self, url: &str, offset: usize) -> Result<ResponseHead, DownloadError>;
+    /// Read from the body of the current response, returning 0 at its end.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, DownloadError>;
+}
+
+/// Receives the downloaded file in order. Data is only ever delivered once, even
+/// across retries.
+pub trait DownloadSink {
+    /// Called once with the file size, before any data.
+    fn begin(&mut self, total: usize) -> Result<(), DownloadError>;
+    fn write(&mut self, data: &[u8]) -> Result<(), DownloadError>;
+    /// Called before waiting `backoff` to retry after `error`. Returning an error
+    /// ends the download instead.
+    fn retrying(
+        &mut self,
+        _error: &DownloadError,
+        _backoff: Duration,
+    ) -> Result<(), DownloadError> {
+        Ok(())
+    }
+}
+
+impl DownloadSink for Vec<u8> {
+    fn begin(&mut self, total: usize) -> Result<(), DownloadError> {
+        self.reserve(total);
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), DownloadError> {
+        self.extend_from_slice(data);
+        Ok(())
+    }
+}
+
+/// Parse a `Content-Range: bytes <start>-<end>/<total>` value into its start, inclusive
+/// end and total size, which is `None` if the server does not know it.
+pub fn parse_content_range(value: &str) -> Option<(usize, usize, Option<usize>)> {
+    let range = value.trim().strip_prefix("bytes ")?;
+    let (span, total) = range.split_once('/')?;
+    let (start, end) = span.split_once('-')?;
+    let total = match total {
+        "*" => None,
+        total => Some(total.parse().ok()?),
+    };
+    Some((start.parse().ok()?, end.parse().ok()?, total))
+}
+
+/// Download `url` into `sink`, returning the number of bytes written.
+pub fn download(
+    client: &mut impl HttpClient,
+    url: &str,
+    config: &DownloadConfig,
+    sink: &mut impl DownloadSink,
+) -> Result<usize, DownloadError> {
+    let mut buf = vec![0; config.chunk_len];
+    let mut transfer = Transfer {
+        total: None,
+        written: 0,
+    };
+    let mut retries = 0;
+
+    loop {
+        let written = transfer.written;
+        let error = match transfer.run(client, url, sink, &mut buf) {
+            Ok(()) => return Ok(transfer.written),
+            Err(e) if !e.is_transient() => return Err(e),
+            Err(e) => e,
+        };
+        if transfer.written > written {
+            retries = 0;
+        }
+        retries += 1;
+        if retries >= config.max_attempts {
+            return Err(DownloadError::RetriesExhausted {
+                attempts: retries,
+                last: Box::new(error),
+            });
+        }
+        let backoff = config.backoff(retries);
+        sink.retrying(&error, backoff)?;
+        thread::sleep(backoff);
+    }
+}
+
+struct Transfer {
+    total: Option<usize>,
+    written: usize,
+}
+
+impl Transfer {
+    /// One request, resuming where the previous one stopped.
+    fn run(
+        &mut self,
+        client: &mut impl HttpClient,
+        url: &str,
+        sink: &mut impl DownloadSink,
+        buf: &mut [u8],
+    ) -> Result<(), DownloadError> {
+        let head = client.get(url, self.written)?;
+        let total = match head.status {
+            200 if self.written == 0 => head.content_length.ok_or(DownloadError::UnknownSize)?,
+            200 => return Err(DownloadError::RangeNotSupported),
+            206 => {
+                let range = head.content_range.as_deref();
+                match range.and_then(parse_content_range) {
+                    Some((start, _, Some(total))) if start == self.written => total,
+                    _ => return Err(DownloadError::InvalidRange),
+                }
+            }
+            status => return Err(DownloadError::Status(status)),
+        };
+
+        match self.total {
+            None => {
+                sink.begin(total)?;
+                self.total = Some(total);
+            }
+            Some(expected) if expected != total => {
+                return Err(DownloadError::SizeChanged {
+                    expected,
+                    actual: total,
+                });
+            }
+            Some(_) => (),
+        }
+
+        while self.written < total {
+            let n = client.read(buf)?;
+            if n == 0 {
+                return Err(DownloadError::Interrupted(format!(
+                    "response ended after {} of {} bytes",
+                    self.written, total
+                )));
+            }
+            let n = n.min(total - self.written);
+            sink.write(&buf[..n])?;
+            self.written += n;
+        }
+        Ok(())
+    }
+}
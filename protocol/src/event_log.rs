@@ -0,0 +1,174 @@
+//! Bounded log of the warnings and errors the firmware ran into, kept across reboots.
+//!
+//! Entries are numbered in order, the numbers continuing across reboots and clears, so
+//! a client can page through the log with [`Command::GetEventLog`] and later fetch only
+//! what is new. A message repeated right after itself is counted instead of stored
+//! again, so an error firing on every sample does not push everything else out.
+//!
+//! [`Command::GetEventLog`]: crate::Command::GetEventLog
+
+use std::collections::VecDeque;
+
+use bincode::Error;
+use serde::{Deserialize, Serialize};
+
+pub const EVENT_LOG_VERSION: u16 = 1;
+/// Oldest entries are dropped beyond this
+pub const EVENT_LOG_CAPACITY: usize = 64;
+/// Longer messages are cut off
+pub const EVENT_LOG_MESSAGE_MAX_LEN: usize = 128;
+pub const EVENT_LOG_SUBSYSTEM_MAX_LEN: usize = 16;
+/// Most entries returned by one [`Command::GetEventLog`], which fit into a frame
+///
+/// [`Command::GetEventLog`]: crate::Command::GetEventLog
+pub const EVENT_LOG_PAGE_LEN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub seq: u32,
+    /// Boot the entry was logged in, see [`Status::boot_count`]
+    ///
+    /// [`Status::boot_count`]: crate::Status::boot_count
+    pub boot_count: u32,
+    /// Device time of the first occurrence, in microseconds since boot
+    pub timestamp_us: u64,
+    pub severity: Severity,
+    /// Firmware module the message came from
+    pub subsystem: String,
+    pub message: String,
+    /// How often the message was logged again right after
+    pub repeated: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum EventLogError {
+    #[error("unknown event log version {0}")]
+    UnknownVersion(u16),
+    #[error("corrupt event log: {0}")]
+    Corrupt(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EventLog {
+    entries: VecDeque<LogEntry>,
+    next_seq: u32,
+}
+
+impl EventLog {
+    pub const fn new() -> Self {
+        Self {
+            entries: VecDeque::new(),
+            next_seq: 0,
+        }
+    }
+
+    pub fn push(
+        &mut self,
+        boot_count: u32,
+        timestamp_us: u64,
+        severity: Severity,
+        subsystem: &str,
+        message: &str,
+    ) {
+        let subsystem = truncate(subsystem, EVENT_LOG_SUBSYSTEM_MAX_LEN);
+        let message = truncate(message, EVENT_LOG_MESSAGE_MAX_LEN);
+        if let Some(last) = self.entries.back_mut() {
+            if last.boot_count == boot_count
+                && last.severity == severity
+                && last.subsystem == subsystem
+                && last.message == message
+            {
+                last.repeated = last.repeated.saturating_add(1);
+                return;
+            }
+        }
+
+        self.entries.push_back(LogEntry {
+            seq: self.next_seq,
+            boot_count,
+            timestamp_us,
+            severity,
+            subsystem: subsystem.to_string(),
+            message: message.to_string(),
+            repeated: 0,
+        });
+        self.next_seq = self.next_seq.wrapping_add(1);
+        while self.entries.len() > EVENT_LOG_CAPACITY {
+            self.entries.pop_front();
+        }
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &LogEntry> {
+        self.entries.iter()
+    }
+
+    /// Up to [`EVENT_LOG_PAGE_LEN`] entries numbered `from` or later.
+    pub fn page(&self, from: u32) -> Vec<LogEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.seq >= from)
+            .take(EVENT_LOG_PAGE_LEN)
+            .cloned()
+            .collect()
+    }
+
+    /// Drop all entries. Numbering continues where it was.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Put the log stored by earlier boots in front of the entries logged since this
+    /// boot started, which are renumbered to follow it. Entries logged before the boot
+    /// count was known carry 0 and get `boot_count`.
+    pub fn restore(&mut self, stored: EventLog, boot_count: u32) {
+        let mut next_seq = stored.next_seq;
+        let mut entries = stored.entries;
+        for mut entry in self.entries.drain(..) {
+            entry.seq = next_seq;
+            next_seq = next_seq.wrapping_add(1);
+            if entry.boot_count == 0 {
+                entry.boot_count = boot_count;
+            }
+            entries.push_back(entry);
+        }
+        while entries.len() > EVENT_LOG_CAPACITY {
+            entries.pop_front();
+        }
+        self.entries = entries;
+        self.next_seq = next_seq;
+    }
+}
+
+/// Encode the log for storage, prefixed with its schema version.
+pub fn encode_event_log(log: &EventLog) -> Result<Vec<u8>, Error> {
+    let mut bytes = EVENT_LOG_VERSION.to_le_bytes().to_vec();
+    bytes.extend(bincode::serialize(log)?);
+    Ok(bytes)
+}
+
+pub fn decode_event_log(bytes: &[u8]) -> Result<EventLog, EventLogError> {
+    let (version, payload) = match bytes {
+        [a, b, payload @ ..] => (u16::from_le_bytes([*a, *b]), payload),
+        _ => return Err(EventLogError::Corrupt("truncated header".into())),
+    };
+    match version {
+        EVENT_LOG_VERSION => {
+            bincode::deserialize(payload).map_err(|e| EventLogError::Corrupt(e.to_string()))
+        }
+        version => Err(EventLogError::UnknownVersion(version)),
+    }
+}
+
+fn truncate(text: &str, max_len: usize) -> &str {
+    let mut end = text.len().min(max_len);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
@@ -0,0 +1,171 @@
+//! Short-term history of the data stream, replayed to subscribers that join late.
+//!
+//! Samples are kept at [`HISTORY_SAMPLE_RATE_HZ`] to bound memory use; beats, events and
+//! BPM changes are all kept. Entries are numbered in the order they were recorded, so a
+//! replay can be sent in chunks while new data keeps coming in, see [`History::read`].
+
+use std::collections::VecDeque;
+
+use crate::packet::*;
+
+/// How far back the history reaches
+pub const HISTORY_SECS: u64 = 10;
+/// Rate at which samples are kept, lower than the sensor sample rate
+pub const HISTORY_SAMPLE_RATE_HZ: u16 = 100;
+
+#[derive(Debug, Clone)]
+enum Entry {
+    /// Raw and filtered value of the same sample, `NaN` if not recorded
+    Sample {
+        index: u64,
+        timestamp_us: u64,
+        raw: f32,
+        filtered: f32,
+    },
+    Bpm(Sample),
+    Beat(Box<BeatEvent>),
+    Event(StampedEvent),
+}
+
+impl Entry {
+    fn timestamp_us(&self) -> u64 {
+        match self {
+            Entry::Sample { timestamp_us, .. } => *timestamp_us,
+            Entry::Bpm(sample) => sample.timestamp_us,
+            Entry::Beat(beat) => beat.timestamp_us,
+            Entry::Event(event) => event.timestamp_us,
+        }
+    }
+
+    fn packets(&self, out: &mut Vec<Packet>) {
+        match self {
+            &Entry::Sample {
+                index,
+                timestamp_us,
+                raw,
+                filtered,
+            } => {
+                let stamp = |value| Sample {
+                    index,
+                    timestamp_us,
+                    value,
+                };
+                if !raw.is_nan() {
+                    out.push(Packet::RawHeartRate(stamp(raw)));
+                }
+                if !filtered.is_nan() {
+                    out.push(Packet::HeartRate(stamp(filtered)));
+                }
+            }
+            Entry::Bpm(sample) => out.push(Packet::Bpm(*sample)),
+            Entry::Beat(beat) => out.push(Packet::Beat(**beat)),
+            Entry::Event(event) => out.push(Packet::Event(*event)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct History {
+    entries: VecDeque<Entry>,
+    /// Number of the front entry
+    first: u64,
+    /// Only samples with an index divisible by this are kept
+    sample_divider: u64,
+    latest_us: u64,
+    last_bpm: Option<f32>,
+}
+
+impl History {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            first: 0,
+            sample_divider: (sample_rate as u64 / HISTORY_SAMPLE_RATE_HZ as u64).max(1),
+            latest_us: 0,
+            last_bpm: None,
+        }
+    }
+
+    /// Only samples with an index divisible by this are kept.
+    pub fn sample_divider(&self) -> u64 {
+        self.sample_divider
+    }
+
+    /// Keep a streamed packet, if it is worth replaying. Status and update packets
+    /// only describe the present and are skipped.
+    pub fn record(&mut self, packet: &Packet) {
+        let entry = match packet {
+            Packet::RawHeartRate(sample) | Packet::HeartRate(sample) => {
+                if sample.index % self.sample_divider != 0 {
+                    return;
+                }
+                let raw = matches!(packet, Packet::RawHeartRate(_));
+                // The raw and filtered value of a sample are sent one after the other
+                if let Some(Entry::Sample {
+                    index,
+                    raw: last_raw,
+                    filtered,
+                    ..
+                }) = self.entries.back_mut()
+                {
+                    if *index == sample.index {
+                        *if raw { last_raw } else { filtered } = sample.value;
+                        return;
+                    }
+                }
+                Entry::Sample {
+                    index: sample.index,
+                    timestamp_us: sample.timestamp_us,
+                    raw: if raw { sample.value } else { f32::NAN },
+                    filtered: if raw { f32::NAN } else { sample.value },
+                }
+            }
+            // BPM is sent with every sample while no beat is detected
+            Packet::Bpm(sample) if self.last_bpm == Some(sample.value) => return,
+            Packet::Bpm(sample) => {
+                self.last_bpm = Some(sample.value);
+                Entry::Bpm(*sample)
+            }
+            Packet::Beat(beat) => Entry::Beat(Box::new(*beat)),
+            Packet::Event(event) => Entry::Event(*event),
+            Packet::Status(_) | Packet::Update(_) => return,
+        };
+
+        self.latest_us = self.latest_us.max(entry.timestamp_us());
+        self.entries.push_back(entry);
+
+        let oldest_us = self.latest_us.saturating_sub(HISTORY_SECS * 1_000_000);
+        while self
+            .entries
+            .front()
+            .is_some_and(|entry| entry.timestamp_us() < oldest_us)
+        {
+            self.entries.pop_front();
+            self.first += 1;
+        }
+    }
+
+    /// Number of the oldest entry still kept.
+    pub fn start(&self) -> u64 {
+        self.first
+    }
+
+    /// Number the next recorded entry will get.
+    pub fn end(&self) -> u64 {
+        self.first + self.entries.len() as u64
+    }
+
+    /// Packets of up to `max` entries, starting at entry `from`, or the oldest one kept
+    /// if that is gone already. Returns the number of the entry to continue from.
+    pub fn read(&self, from: u64, max: usize) -> (Vec<Packet>, u64) {
+        let from = from.max(self.first);
+        let mut packets = Vec::new();
+        let entries = self.entries.iter().skip((from - self.first) as usize);
+        let mut next = from;
+        for entry in entries.take(max) {
+            entry.packets(&mut packets);
+            next += 1;
+        }
+        (packets, next)
+    }
+}
@@ -0,0 +1,81 @@
+//! Signed firmware images.
+//!
+//! Release images are published together with their SHA-256 digest and an Ed25519
+//! signature of that digest. Devices only boot images whose digest matches and whose
+//! signature verifies against the public key compiled into the running firmware.
+
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+pub const IMAGE_DIGEST_LEN: usize = 32;
+pub const IMAGE_SIGNATURE_LEN: usize = 64;
+
+pub type ImageDigest = [u8; IMAGE_DIGEST_LEN];
+
+pub fn image_digest(image: &[u8]) -> ImageDigest {
+    Sha256::digest(image).into()
+}
+
+pub fn sign_image_digest(
+    signing_key: &[u8; 32],
+    digest: &ImageDigest,
+) -> [u8; IMAGE_SIGNATURE_LEN] {
+    SigningKey::from_bytes(signing_key).sign(digest).to_bytes()
+}
+
+pub fn verify_image_signature(
+    public_key: &[u8; 32],
+    digest: &ImageDigest,
+    signature: &[u8],
+) -> bool {
+    let Ok(signature) = signature.try_into() else {
+        return false;
+    };
+    VerifyingKey::from_bytes(public_key)
+        .and_then(|key| key.verify_strict(digest, &ed25519_dalek::Signature::from_bytes(signature)))
+        .is_ok()
+}
+
+/// Format a digest the way `sha256sum` does, as published next to release images.
+pub fn format_image_digest(digest: &ImageDigest, file_name: &str) -> String {
+    let hex: String = digest.iter().map(|b| format!("{b:02x}")).collect();
+    format!("{hex}  {file_name}\n")
+}
+
+/// Parse the digest from the first word of a `sha256sum` style line.
+pub fn parse_image_digest(text: &str) -> Option<ImageDigest> {
+    decode_hex(text.split_whitespace().next()?)
+}
+
+/// Decode exactly `N` hex encoded bytes, such as a key. Trailing whitespace, like the
+/// newline ending a key file, is ignored. Usable in constants, so a key compiled into
+/// the firmware is checked when it is built.
+pub const fn decode_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    let hex = hex.as_bytes();
+    let mut len = hex.len();
+    while len > 0 && hex[len - 1].is_ascii_whitespace() {
+        len -= 1;
+    }
+    if len != 2 * N {
+        return None;
+    }
+    let mut bytes = [0; N];
+    let mut i = 0;
+    while i < N {
+        let (Some(high), Some(low)) = (hex_digit(hex[2 * i]), hex_digit(hex[2 * i + 1])) else {
+            return None;
+        };
+        bytes[i] = high << 4 | low;
+        i += 1;
+    }
+    Some(bytes)
+}
+
+const fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
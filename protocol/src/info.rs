@@ -0,0 +1,157 @@
+//! Read-back replies describing the current state of the device.
+
+use std::net::{Ipv4Addr, SocketAddr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Settings;
+
+/// All persisted settings and where the data stream currently goes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceConfig {
+    pub settings: Settings,
+    pub udp_target: Option<SocketAddr>,
+}
+
+/// Constants of the heartbeat detector running on the device.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DetectorParams {
+    pub sample_rate: f32,
+    pub finger_threshold: f32,
+    pub finger_cooldown_ms: u32,
+    pub edge_threshold: f32,
+    pub low_pass_cut_off: f32,
+    pub high_pass_cut_off: f32,
+    pub min_ibi_ms: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NetworkInfo {
+    pub connected: bool,
+    pub mac: [u8; 6],
+    pub ip: Option<Ipv4Addr>,
+    pub netmask_bits: u8,
+    pub gateway: Option<Ipv4Addr>,
+    pub dns: Option<Ipv4Addr>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SlotState {
+    Valid,
+    Invalid,
+    Unverified,
+    Unknown,
+}
+
+/// One of the OTA app partitions.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SlotInfo {
+    pub label: String,
+    pub state: SlotState,
+    /// Version from the image header, `None` if the slot holds no valid image
+    pub version: Option<String>,
+}
+
+/// Why the device last reset, as reported by ESP-IDF.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResetReason {
+    #[default]
+    Unknown,
+    PowerOn,
+    ExternalPin,
+    /// Restart requested by the firmware, e.g. after an update
+    Software,
+    Panic,
+    InterruptWatchdog,
+    TaskWatchdog,
+    Watchdog,
+    DeepSleep,
+    Brownout,
+    Sdio,
+    Usb,
+    Jtag,
+    Efuse,
+    PowerGlitch,
+    CpuLockup,
+}
+
+/// Which OTA slot runs now and which one boots next, and why the device last reset.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BootInfo {
+    /// Label of the slot the firmware is running from
+    pub running: String,
+    /// Label of the slot the bootloader starts on the next reset
+    pub next_boot: String,
+    /// Both OTA slots, in partition table order
+    pub slots: Vec<SlotInfo>,
+    pub reset_reason: ResetReason,
+    /// Boots since the counter was first stored in NVS
+    pub boot_count: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PartitionsInfo {
+    pub running: SlotInfo,
+    /// The slot the next update will be written to
+    pub update: SlotInfo,
+}
+
+/// Result of the self-test run by a freshly updated image on its first boot.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum UpdateOutcome {
+    /// The image passed its self-test and was marked valid
+    Verified { version: String },
+    /// The image failed its self-test or crashed before finishing it, and the device
+    /// went back to the previous image
+    RolledBack {
+        version: Option<String>,
+        reason: String,
+    },
+}
+
+/// Whether the release manifest announces a newer firmware than the running one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UpdateCheck {
+    pub running: [u8; 3],
+    pub latest: [u8; 3],
+    /// Image URL of the latest release, to be passed to [`Command::Update`]
+    ///
+    /// [`Command::Update`]: crate::Command::Update
+    pub image: String,
+    pub available: bool,
+}
+
+/// What is known about the last crash. Both parts are kept until the core dump is
+/// erased, the panic message is replaced by the next panic.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CrashReport {
+    /// Size of the core dump stored in the `coredump` partition
+    pub core_dump_size: Option<u32>,
+    pub last_panic: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SensorHealth {
+    pub heart_ok: bool,
+    pub haptic_ok: bool,
+    pub display_ok: bool,
+    pub finger_detected: bool,
+    pub samples_read: u64,
+    pub fifo_errors: u32,
+    /// Device time of the last sample read from the sensor, in microseconds since boot
+    pub last_sample_us: Option<u64>,
+}
+
+/// A client connected to the command server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClientInfo {
+    pub id: u32,
+    pub addr: SocketAddr,
+    /// Device time of connection, in microseconds since boot
+    pub connected_at_us: u64,
+    /// Device time of the last received command, in microseconds since boot
+    pub last_active_us: u64,
+    pub requests: u32,
+    /// Sends bare legacy command bytes, which exempts it from the idle timeout
+    pub legacy: bool,
+}
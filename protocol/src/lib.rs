@@ -0,0 +1,43 @@
+//! Wire types shared by the esp-pulser firmware and its host-side clients, along with
+//! the parts of the firmware logic that can be tested on the host, such as resumable
+//! [`download`]s.
+//!
+//! All types are encoded with bincode's default options, see [`encode`] and [`decode`].
+//! The encoding of every type is pinned by the tests in `tests/wire.rs`, so any change
+//! that breaks compatibility with deployed devices or GUI builds fails loudly.
+
+mod auth;
+mod command;
+mod download;
+mod event_log;
+mod history;
+mod image;
+mod info;
+mod packet;
+mod recording;
+mod settings;
+mod subscription;
+mod version;
+
+pub use auth::*;
+pub use command::*;
+pub use download::*;
+pub use event_log::*;
+pub use history::*;
+pub use image::*;
+pub use info::*;
+pub use packet::*;
+pub use recording::*;
+pub use settings::*;
+pub use subscription::*;
+pub use version::*;
+
+pub use bincode::Error;
+
+pub fn encode<T: serde::Serialize>(value: &T) -> Result<Vec<u8>, Error> {
+    bincode::serialize(value)
+}
+
+pub fn decode<'a, T: serde::Deserialize<'a>>(bytes: &'a [u8]) -> Result<T, Error> {
+    bincode::deserialize(bytes)
+}
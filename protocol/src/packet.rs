@@ -0,0 +1,123 @@
+//! Packets streamed by the device over UDP.
+
+use serde::{Deserialize, Serialize};
+
+use crate::info::ResetReason;
+use crate::recording::SessionEvent;
+
+/// Fields must only ever be appended: legacy GUI builds decode the prefix they know
+/// and ignore the rest.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Status {
+    pub version: [u8; 3],
+    pub connected: bool,
+    pub display_ok: bool,
+    pub haptic_ok: bool,
+    pub heart_ok: bool,
+    pub led_amplitude: u8,
+    pub haptic_amplitude: u8,
+    /// Label of the OTA slot the firmware is running from
+    pub running_slot: String,
+    pub reset_reason: ResetReason,
+    pub boot_count: u32,
+    /// A core dump of an earlier crash is stored, see [`Command::GetCrashReport`]
+    ///
+    /// [`Command::GetCrashReport`]: crate::Command::GetCrashReport
+    pub core_dump: bool,
+}
+
+impl Status {
+    pub fn new(version: [u8; 3]) -> Self {
+        Self {
+            version,
+            connected: false,
+            display_ok: false,
+            haptic_ok: false,
+            heart_ok: false,
+            led_amplitude: 0,
+            haptic_amplitude: 0,
+            running_slot: String::new(),
+            reset_reason: ResetReason::Unknown,
+            boot_count: 0,
+            core_dump: false,
+        }
+    }
+}
+
+/// A single streamed value, stamped with the index of the sensor sample it was
+/// derived from and the device time at which that sample was read.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Sample {
+    pub index: u64,
+    pub timestamp_us: u64,
+    pub value: f32,
+}
+
+/// A detected heartbeat. `timestamp_us` is the time of the pulse wave peak, while
+/// `sample_index` is the sample at which the detector confirmed the beat.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BeatEvent {
+    pub sample_index: u64,
+    pub timestamp_us: u64,
+    pub ibi_ms: f32,
+    pub amplitude: f32,
+    pub confidence: f32,
+}
+
+/// A session event, stamped like the sample read last before it happened.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StampedEvent {
+    pub sample_index: u64,
+    pub timestamp_us: u64,
+    pub event: SessionEvent,
+}
+
+/// Stage of a firmware update running in the background.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum UpdateState {
+    /// No update has been started since boot
+    #[default]
+    Idle,
+    /// The image is being transferred into the update slot
+    Downloading,
+    /// The transferred image is checked against its signed digest
+    Verifying,
+    /// The update slot is finalised and made the boot partition
+    Writing,
+    /// The device is about to restart into the new image
+    Done,
+    Failed(String),
+    Cancelled,
+    /// The image has the same version as the running firmware and was not installed
+    UpToDate,
+}
+
+/// Progress of the background firmware update, streamed whenever it changes.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UpdateStatus {
+    pub state: UpdateState,
+    /// Percentage of the image transferred so far
+    pub progress: u8,
+}
+
+impl UpdateStatus {
+    pub fn is_active(&self) -> bool {
+        matches!(
+            self.state,
+            UpdateState::Downloading | UpdateState::Verifying | UpdateState::Writing
+        )
+    }
+}
+
+/// Variants are encoded by their position, so new ones must only ever be appended.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Packet {
+    Status(Status),
+    RawHeartRate(Sample),
+    Bpm(Sample),
+    HeartRate(Sample),
+    Beat(BeatEvent),
+    Update(UpdateStatus),
+    /// Sent along with the status stream
+    Event(StampedEvent),
+}
@@ -0,0 +1,304 @@
+//! Compact binary format of the session recordings kept on the device.
+//!
+//! A recording starts with a [`RecordingHeader`] and continues with one record per
+//! sample, beat or event. Each record is a tag byte followed by its fields; sample
+//! indices, timestamps and raw values are stored as zigzag LEB128 deltas from the
+//! previous record, so a raw sample at 400 Hz takes about four bytes. Records are
+//! self-delimiting, so a file cut short by a reset loses at most its last record.
+
+use serde::{Deserialize, Serialize};
+
+use crate::packet::*;
+
+pub const RECORDING_MAGIC: [u8; 4] = *b"PLSR";
+pub const RECORDING_FORMAT_VERSION: u8 = 1;
+pub const RECORDING_HEADER_LEN: usize = 20;
+
+/// The sample directly follows the previous one
+const TAG_SAMPLE: u8 = 0;
+/// The sample index is stored as well, after a gap or at the start
+const TAG_SAMPLE_AT: u8 = 1;
+const TAG_BEAT: u8 = 2;
+const TAG_EVENT: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecordingHeader {
+    pub firmware_version: [u8; 3],
+    pub sample_rate: f32,
+    /// Device time the recording started at, in microseconds since boot
+    pub started_us: u64,
+}
+
+/// Something that happened during a session, besides samples and beats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SessionEvent {
+    FingerDetected,
+    FingerLost,
+    /// The ethernet link came up and a DHCP lease was obtained
+    Connected,
+    Disconnected,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Record {
+    /// A raw sensor reading
+    Sample(Sample),
+    Beat(BeatEvent),
+    Event {
+        timestamp_us: u64,
+        event: SessionEvent,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum RecordingError {
+    #[error("not a recording")]
+    BadMagic,
+    #[error("unknown recording format {0}")]
+    UnknownFormat(u8),
+    #[error("recording ends in the middle of a record")]
+    Truncated,
+    #[error("unknown record tag {0}")]
+    UnknownTag(u8),
+    #[error("unknown event {0}")]
+    UnknownEvent(u8),
+}
+
+/// A stored recording, as listed by the device.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordingInfo {
+    pub name: String,
+    pub size: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordingList {
+    pub recordings: Vec<RecordingInfo>,
+    /// Name of the recording being written, if any
+    pub active: Option<String>,
+    pub used_bytes: u32,
+    pub total_bytes: u32,
+}
+
+impl SessionEvent {
+    fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            0 => SessionEvent::FingerDetected,
+            1 => SessionEvent::FingerLost,
+            2 => SessionEvent::Connected,
+            3 => SessionEvent::Disconnected,
+            _ => return None,
+        })
+    }
+}
+
+/// Delta state shared by the encoder and decoder.
+#[derive(Debug, Clone, Default)]
+struct Deltas {
+    index: Option<u64>,
+    timestamp_us: u64,
+    value: i64,
+}
+
+/// Appends records to a buffer, which the caller writes out whenever convenient.
+#[derive(Debug, Clone)]
+pub struct RecordingEncoder {
+    last: Deltas,
+}
+
+impl RecordingEncoder {
+    /// Start a recording by writing its header to `out`.
+    pub fn new(header: &RecordingHeader, out: &mut Vec<u8>) -> Self {
+        out.extend_from_slice(&RECORDING_MAGIC);
+        out.push(RECORDING_FORMAT_VERSION);
+        out.extend_from_slice(&header.firmware_version);
+        out.extend_from_slice(&header.sample_rate.to_le_bytes());
+        out.extend_from_slice(&header.started_us.to_le_bytes());
+        Self {
+            last: Deltas {
+                timestamp_us: header.started_us,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Record a raw sample. Raw readings are integers, the value is rounded.
+    pub fn sample(&mut self, out: &mut Vec<u8>, sample: &Sample) {
+        match self.last.index {
+            Some(index) if sample.index == index + 1 => out.push(TAG_SAMPLE),
+            last => {
+                out.push(TAG_SAMPLE_AT);
+                write_signed(out, sample.index as i64 - last.unwrap_or(0) as i64);
+            }
+        }
+        self.last.index = Some(sample.index);
+        self.timestamp(out, sample.timestamp_us);
+        let value = sample.value.round() as i64;
+        write_signed(out, value - self.last.value);
+        self.last.value = value;
+    }
+
+    pub fn beat(&mut self, out: &mut Vec<u8>, beat: &BeatEvent) {
+        out.push(TAG_BEAT);
+        write_signed(
+            out,
+            beat.sample_index as i64 - self.last.index.unwrap_or(0) as i64,
+        );
+        self.timestamp(out, beat.timestamp_us);
+        out.extend_from_slice(&beat.ibi_ms.to_le_bytes());
+        out.extend_from_slice(&beat.amplitude.to_le_bytes());
+        out.extend_from_slice(&beat.confidence.to_le_bytes());
+    }
+
+    pub fn event(&mut self, out: &mut Vec<u8>, timestamp_us: u64, event: SessionEvent) {
+        out.push(TAG_EVENT);
+        self.timestamp(out, timestamp_us);
+        out.push(event as u8);
+    }
+
+    fn timestamp(&mut self, out: &mut Vec<u8>, timestamp_us: u64) {
+        write_signed(out, timestamp_us as i64 - self.last.timestamp_us as i64);
+        self.last.timestamp_us = timestamp_us;
+    }
+}
+
+/// Reads the records of a recording in order. After an error, nothing more is read.
+#[derive(Debug, Clone)]
+pub struct RecordingDecoder<'a> {
+    pub header: RecordingHeader,
+    bytes: &'a [u8],
+    last: Deltas,
+    failed: bool,
+}
+
+impl<'a> RecordingDecoder<'a> {
+    pub fn new(bytes: &'a [u8]) -> Result<Self, RecordingError> {
+        if bytes.len() < RECORDING_HEADER_LEN {
+            return Err(match bytes.starts_with(&RECORDING_MAGIC) {
+                true => RecordingError::Truncated,
+                false => RecordingError::BadMagic,
+            });
+        }
+        if bytes[..4] != RECORDING_MAGIC {
+            return Err(RecordingError::BadMagic);
+        }
+        if bytes[4] != RECORDING_FORMAT_VERSION {
+            return Err(RecordingError::UnknownFormat(bytes[4]));
+        }
+        let header = RecordingHeader {
+            firmware_version: [bytes[5], bytes[6], bytes[7]],
+            sample_rate: f32::from_le_bytes(bytes[8..12].try_into().unwrap()),
+            started_us: u64::from_le_bytes(bytes[12..20].try_into().unwrap()),
+        };
+        Ok(Self {
+            header,
+            bytes: &bytes[RECORDING_HEADER_LEN..],
+            last: Deltas {
+                timestamp_us: header.started_us,
+                ..Default::default()
+            },
+            failed: false,
+        })
+    }
+
+    fn record(&mut self) -> Result<Record, RecordingError> {
+        let tag = self.byte()?;
+        match tag {
+            TAG_SAMPLE | TAG_SAMPLE_AT => {
+                let last_index = self.last.index.unwrap_or(0);
+                let index = match tag {
+                    TAG_SAMPLE => last_index + 1,
+                    _ => add(last_index, self.signed()?),
+                };
+                let timestamp_us = self.timestamp()?;
+                let value = self.last.value + self.signed()?;
+                self.last.index = Some(index);
+                self.last.value = value;
+                Ok(Record::Sample(Sample {
+                    index,
+                    timestamp_us,
+                    value: value as f32,
+                }))
+            }
+            TAG_BEAT => {
+                let sample_index = add(self.last.index.unwrap_or(0), self.signed()?);
+                Ok(Record::Beat(BeatEvent {
+                    sample_index,
+                    timestamp_us: self.timestamp()?,
+                    ibi_ms: self.f32()?,
+                    amplitude: self.f32()?,
+                    confidence: self.f32()?,
+                }))
+            }
+            TAG_EVENT => {
+                let timestamp_us = self.timestamp()?;
+                let event = self.byte()?;
+                let event =
+                    SessionEvent::from_u8(event).ok_or(RecordingError::UnknownEvent(event))?;
+                Ok(Record::Event {
+                    timestamp_us,
+                    event,
+                })
+            }
+            tag => Err(RecordingError::UnknownTag(tag)),
+        }
+    }
+
+    fn timestamp(&mut self) -> Result<u64, RecordingError> {
+        self.last.timestamp_us = add(self.last.timestamp_us, self.signed()?);
+        Ok(self.last.timestamp_us)
+    }
+
+    fn byte(&mut self) -> Result<u8, RecordingError> {
+        let (&byte, rest) = self.bytes.split_first().ok_or(RecordingError::Truncated)?;
+        self.bytes = rest;
+        Ok(byte)
+    }
+
+    fn f32(&mut self) -> Result<f32, RecordingError> {
+        if self.bytes.len() < 4 {
+            return Err(RecordingError::Truncated);
+        }
+        let (value, rest) = self.bytes.split_at(4);
+        self.bytes = rest;
+        Ok(f32::from_le_bytes(value.try_into().unwrap()))
+    }
+
+    fn signed(&mut self) -> Result<i64, RecordingError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok((value >> 1) as i64 ^ -((value & 1) as i64));
+            }
+        }
+        Err(RecordingError::Truncated)
+    }
+}
+
+impl Iterator for RecordingDecoder<'_> {
+    type Item = Result<Record, RecordingError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.bytes.is_empty() {
+            return None;
+        }
+        let record = self.record();
+        self.failed = record.is_err();
+        Some(record)
+    }
+}
+
+fn write_signed(out: &mut Vec<u8>, value: i64) {
+    let mut value = ((value << 1) ^ (value >> 63)) as u64;
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn add(base: u64, delta: i64) -> u64 {
+    base.wrapping_add_signed(delta)
+}
@@ -0,0 +1,263 @@
+//! User settings, persisted on the device as a single NVS blob.
+//!
+//! The blob starts with the schema version as a little endian `u16`, followed by the
+//! bincode encoded [`Settings`]. Older layouts are migrated in [`decode_settings`], and
+//! devices that predate the blob are migrated from their loose NVS keys with
+//! [`Settings::from_legacy`].
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{DownloadConfig, Error};
+
+/// Schema version written by [`encode_settings`]
+pub const SETTINGS_VERSION: u16 = 1;
+/// Longest accepted release URL, in bytes
+pub const RELEASE_URL_MAX_LEN: usize = 255;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    pub sensor: SensorSettings,
+    pub detector: DetectorSettings,
+    pub haptic: HapticSettings,
+    pub network: NetworkSettings,
+    pub display: DisplaySettings,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SensorSettings {
+    /// Drive current of the sensor LED, in steps of 0.2 mA
+    pub led_amplitude: u8,
+}
+
+impl Default for SensorSettings {
+    fn default() -> Self {
+        Self { led_amplitude: 35 }
+    }
+}
+
+/// Tuning of the heartbeat detector, see [`DetectorParams`](crate::DetectorParams).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DetectorSettings {
+    /// Raw readings above this mean a finger is on the sensor
+    pub finger_threshold: f32,
+    /// How long a finger has to stay on the sensor before beats are detected
+    pub finger_cooldown_ms: u32,
+    /// Slope of the filtered signal below which a falling edge counts as a beat
+    pub edge_threshold: f32,
+    /// Shortest accepted interval between beats
+    pub min_ibi_ms: u32,
+}
+
+impl Default for DetectorSettings {
+    fn default() -> Self {
+        Self {
+            finger_threshold: 100_000.0,
+            finger_cooldown_ms: 500,
+            edge_threshold: -2000.0,
+            min_ibi_ms: 300,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HapticSettings {
+    /// Pulse the motor on every detected beat
+    pub enabled: bool,
+    /// Overdrive clamp voltage of the motor driver
+    pub amplitude: u8,
+    pub effect: HapticEffect,
+}
+
+impl Default for HapticSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            amplitude: 255,
+            effect: HapticEffect::default(),
+        }
+    }
+}
+
+/// Waveform the motor plays on a beat, a selection of the DRV2605L library effects.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HapticEffect {
+    #[default]
+    Pulsing,
+    StrongClick,
+    SharpClick,
+    SoftBump,
+    DoubleClick,
+    Buzz,
+}
+
+impl HapticEffect {
+    pub const ALL: [HapticEffect; 6] = [
+        HapticEffect::Pulsing,
+        HapticEffect::StrongClick,
+        HapticEffect::SharpClick,
+        HapticEffect::SoftBump,
+        HapticEffect::DoubleClick,
+        HapticEffect::Buzz,
+    ];
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NetworkSettings {
+    /// Base URL the release manifest is fetched from, `None` for the official releases
+    pub release_url: Option<String>,
+    /// Connect and read timeout of update downloads
+    pub download_timeout_secs: u16,
+    /// Attempts in a row without progress before a download gives up
+    pub download_attempts: u8,
+    /// Longest delay between two download attempts, which start 1 s apart and double
+    pub download_max_backoff_secs: u16,
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        let config = DownloadConfig::default();
+        Self {
+            release_url: None,
+            download_timeout_secs: config.timeout.as_secs() as u16,
+            download_attempts: config.max_attempts as u8,
+            download_max_backoff_secs: config.max_backoff.as_secs() as u16,
+        }
+    }
+}
+
+impl NetworkSettings {
+    /// Download tuning with these timeouts and retries, and the default chunk length.
+    pub fn download_config(&self) -> DownloadConfig {
+        DownloadConfig {
+            timeout: Duration::from_secs(self.download_timeout_secs.into()),
+            max_attempts: self.download_attempts.into(),
+            max_backoff: Duration::from_secs(self.download_max_backoff_secs.into()),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DisplaySettings {
+    /// Draw the live screen on the TFT; when off, it is only updated by the menu
+    pub enabled: bool,
+    /// How long the backlight stays on after a button press, 0 to keep it on
+    pub backlight_secs: u8,
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            backlight_secs: 10,
+        }
+    }
+}
+
+/// Settings stored under their own NVS keys, before the settings blob existed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LegacySettings {
+    pub led_amplitude: Option<u8>,
+    pub haptic_amplitude: Option<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum SettingsError {
+    #[error("settings are empty")]
+    Empty,
+    #[error("unknown settings version {0}")]
+    UnknownVersion(u16),
+    #[error("corrupt settings: {0}")]
+    Corrupt(String),
+    #[error("invalid setting {field}: {reason}")]
+    Invalid {
+        field: &'static str,
+        reason: &'static str,
+    },
+}
+
+impl Settings {
+    /// Take over the values of the legacy keys, leaving everything else at its default.
+    pub fn from_legacy(legacy: LegacySettings) -> Self {
+        let mut settings = Settings::default();
+        if let Some(led_amplitude) = legacy.led_amplitude {
+            settings.sensor.led_amplitude = led_amplitude;
+        }
+        if let Some(amplitude) = legacy.haptic_amplitude {
+            settings.haptic.amplitude = amplitude;
+        }
+        settings
+    }
+
+    /// Check every field, returning the first one out of range.
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        let invalid = |field, reason| Err(SettingsError::Invalid { field, reason });
+
+        let detector = &self.detector;
+        if !(detector.finger_threshold.is_finite() && detector.finger_threshold > 0.0) {
+            return invalid("detector.finger_threshold", "must be positive");
+        }
+        if detector.finger_cooldown_ms > 10_000 {
+            return invalid("detector.finger_cooldown_ms", "must be at most 10 s");
+        }
+        if !(detector.edge_threshold.is_finite() && detector.edge_threshold < 0.0) {
+            return invalid("detector.edge_threshold", "must be negative");
+        }
+        // 30 to 200 BPM are the rates the detector reports at all
+        if !(200..=2000).contains(&detector.min_ibi_ms) {
+            return invalid("detector.min_ibi_ms", "must be between 200 and 2000 ms");
+        }
+
+        if let Some(url) = &self.network.release_url {
+            if url.len() > RELEASE_URL_MAX_LEN {
+                return invalid("network.release_url", "is too long");
+            }
+            if !(url.starts_with("http://") || url.starts_with("https://")) {
+                return invalid("network.release_url", "must be an HTTP(S) URL");
+            }
+        }
+        let network = &self.network;
+        if !(1..=120).contains(&network.download_timeout_secs) {
+            return invalid(
+                "network.download_timeout_secs",
+                "must be between 1 and 120 s",
+            );
+        }
+        if !(1..=20).contains(&network.download_attempts) {
+            return invalid("network.download_attempts", "must be between 1 and 20");
+        }
+        if !(1..=300).contains(&network.download_max_backoff_secs) {
+            return invalid(
+                "network.download_max_backoff_secs",
+                "must be between 1 and 300 s",
+            );
+        }
+        Ok(())
+    }
+}
+
+pub fn encode_settings(settings: &Settings) -> Result<Vec<u8>, Error> {
+    let mut bytes = SETTINGS_VERSION.to_le_bytes().to_vec();
+    bytes.extend(bincode::serialize(settings)?);
+    Ok(bytes)
+}
+
+/// Decode and validate a settings blob of any known schema version.
+pub fn decode_settings(bytes: &[u8]) -> Result<Settings, SettingsError> {
+    let (version, payload) = match bytes {
+        [] => return Err(SettingsError::Empty),
+        [a, b, payload @ ..] => (u16::from_le_bytes([*a, *b]), payload),
+        _ => return Err(SettingsError::Corrupt("truncated header".into())),
+    };
+    // Migrations from older versions go here, each one converting to the current layout
+    let settings: Settings = match version {
+        SETTINGS_VERSION => {
+            bincode::deserialize(payload).map_err(|e| SettingsError::Corrupt(e.to_string()))?
+        }
+        version => return Err(SettingsError::UnknownVersion(version)),
+    };
+    settings.validate()?;
+    Ok(settings)
+}
@@ -0,0 +1,49 @@
+//! Subscriptions to the UDP data stream.
+
+use std::net::SocketAddr;
+
+use serde::{Deserialize, Serialize};
+
+/// Selection of packet kinds a subscriber receives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Streams {
+    pub status: bool,
+    pub raw: bool,
+    pub filtered: bool,
+    pub bpm: bool,
+    pub beats: bool,
+}
+
+impl Streams {
+    pub const ALL: Streams = Streams {
+        status: true,
+        raw: true,
+        filtered: true,
+        bpm: true,
+        beats: true,
+    };
+}
+
+/// Request to stream packets to `port` on the host that sent the command. The
+/// subscriber must keep sending datagrams from that port to the device's UDP port,
+/// otherwise it is dropped after [`SUBSCRIBER_TIMEOUT_SECS`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Subscription {
+    pub port: u16,
+    pub streams: Streams,
+    /// Rate of raw and filtered sample packets, capped at the sensor sample rate
+    pub sample_rate_hz: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SubscriberInfo {
+    pub addr: SocketAddr,
+    pub streams: Streams,
+    pub sample_rate_hz: u16,
+    /// Registered with the legacy UDP port command, which never expires
+    pub legacy: bool,
+}
+
+pub const DATA_PORT: u16 = 3333;
+pub const SUBSCRIBER_TIMEOUT_SECS: u64 = 10;
+pub const MAX_SUBSCRIBERS: usize = 4;
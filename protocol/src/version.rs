@@ -0,0 +1,99 @@
+//! Firmware versions and the release manifest.
+//!
+//! Every release publishes a small manifest next to its images, naming the version
+//! and the image URL, so devices can tell whether an update is available without
+//! downloading the image itself.
+
+use std::cmp::Ordering;
+
+/// File name of the manifest, relative to the release base URL.
+pub const RELEASE_MANIFEST_NAME: &str = "manifest.txt";
+
+/// Parse a `major.minor.patch` version, as found in release tags and image headers.
+/// A leading `v` and pre-release or build suffixes are ignored.
+pub fn parse_version(text: &str) -> Option<[u8; 3]> {
+    let text = text.trim();
+    let text = text.strip_prefix('v').unwrap_or(text);
+    let core = text.split(['-', '+']).next()?;
+    let mut parts = core.split('.');
+    let mut version = [0; 3];
+    for part in version.iter_mut() {
+        *part = parts.next()?.parse().ok()?;
+    }
+    match parts.next() {
+        Some(_) => None,
+        None => Some(version),
+    }
+}
+
+pub fn format_version(version: [u8; 3]) -> String {
+    format!("{}.{}.{}", version[0], version[1], version[2])
+}
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum VersionError {
+    #[error("firmware {} is already installed", format_version(*.0))]
+    SameVersion([u8; 3]),
+    #[error(
+        "refusing to downgrade from {} to {}",
+        format_version(*running),
+        format_version(*incoming)
+    )]
+    Downgrade { running: [u8; 3], incoming: [u8; 3] },
+    #[error("image version {0:?} is not a valid version")]
+    Invalid(String),
+}
+
+/// Check whether an image with version `incoming` should replace the running firmware.
+/// Only newer versions are installed, unless `force` is set.
+pub fn check_image_version(
+    running: [u8; 3],
+    incoming: &str,
+    force: bool,
+) -> Result<(), VersionError> {
+    if force {
+        return Ok(());
+    }
+    let incoming = parse_version(incoming).ok_or_else(|| VersionError::Invalid(incoming.into()))?;
+    match incoming.cmp(&running) {
+        Ordering::Greater => Ok(()),
+        Ordering::Equal => Err(VersionError::SameVersion(running)),
+        Ordering::Less => Err(VersionError::Downgrade { running, incoming }),
+    }
+}
+
+/// Contents of the release manifest, one `key=value` pair per line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReleaseManifest {
+    pub version: [u8; 3],
+    /// URL of the OTA image, with its `.sha256` and `.sig` files next to it
+    pub image: String,
+}
+
+/// Parse a release manifest, ignoring unknown keys, blank lines and `#` comments.
+pub fn parse_release_manifest(text: &str) -> Option<ReleaseManifest> {
+    let mut version = None;
+    let mut image = None;
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once('=')? {
+            ("version", value) => version = Some(parse_version(value)?),
+            ("image", value) => image = Some(value.trim().to_string()),
+            _ => (),
+        }
+    }
+    Some(ReleaseManifest {
+        version: version?,
+        image: image?,
+    })
+}
+
+pub fn format_release_manifest(manifest: &ReleaseManifest) -> String {
+    format!(
+        "version={}\nimage={}\n",
+        format_version(manifest.version),
+        manifest.image
+    )
+}
@@ -0,0 +1,95 @@
+use esp_pulser_protocol::*;
+
+const SECRET: PairingSecret = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25,
+    26, 27, 28, 29, 30, 31,
+];
+const CHALLENGE: Challenge = [0xAA; CHALLENGE_LEN];
+
+#[test]
+fn response_is_hmac_sha256() {
+    assert_eq!(
+        auth_response(&SECRET, &CHALLENGE),
+        [
+            67, 153, 14, 22, 119, 156, 78, 159, 211, 77, 94, 38, 234, 66, 220, 57, 190, 65, 28, 88,
+            40, 208, 41, 143, 237, 61, 90, 205, 237, 233, 147, 46,
+        ]
+    );
+}
+
+#[test]
+fn verification() {
+    let response = auth_response(&SECRET, &CHALLENGE);
+    assert!(verify_auth_response(&SECRET, &CHALLENGE, &response));
+
+    let mut tampered = response;
+    tampered[0] ^= 1;
+    assert!(!verify_auth_response(&SECRET, &CHALLENGE, &tampered));
+    assert!(!verify_auth_response(
+        &[0; PAIRING_SECRET_LEN],
+        &CHALLENGE,
+        &response
+    ));
+    assert!(!verify_auth_response(
+        &SECRET,
+        &[0; CHALLENGE_LEN],
+        &response
+    ));
+}
+
+#[test]
+fn privileged_commands() {
+    assert!(Command::Restart.is_privileged());
+    assert!(Command::Update("0.6.4".into()).is_privileged());
+    assert!(Command::SetLedAmplitude(1).is_privileged());
+    assert!(Command::SetHapticAmplitude(1).is_privileged());
+    assert!(Command::Pair(SECRET).is_privileged());
+    assert!(Command::UploadBegin {
+        size: 1,
+        sha256: [0; 32],
+        signature: Vec::new(),
+        force: false,
+    }
+    .is_privileged());
+    assert!(Command::CancelUpdate.is_privileged());
+    assert!(Command::ForceUpdate("0.6.4".into()).is_privileged());
+    assert!(Command::SetReleaseUrl(String::new()).is_privileged());
+    assert!(Command::SwitchBootSlot.is_privileged());
+    assert!(Command::ImportSettings(Settings::default()).is_privileged());
+    assert!(Command::FactoryReset.is_privileged());
+    assert!(Command::StartRecording.is_privileged());
+    assert!(Command::DeleteRecording("r".into()).is_privileged());
+    assert!(Command::ReadCoreDump(0).is_privileged());
+    assert!(Command::EraseCoreDump.is_privileged());
+    assert!(Command::ClearEventLog.is_privileged());
+
+    assert!(!Command::Hello.is_privileged());
+    assert!(!Command::GetUpdateStatus.is_privileged());
+    assert!(!Command::CheckForUpdate.is_privileged());
+    assert!(!Command::GetBootInfo.is_privileged());
+    assert!(!Command::ExportSettings.is_privileged());
+    assert!(!Command::ListRecordings.is_privileged());
+    assert!(!Command::ReplayHistory(3334).is_privileged());
+    assert!(!Command::GetCrashReport.is_privileged());
+    assert!(!Command::GetEventLog(0).is_privileged());
+    assert!(!Command::GetConfig.is_privileged());
+    assert!(!Command::ConnectUdp(3333).is_privileged());
+    assert!(!Command::AuthChallenge.is_privileged());
+    assert!(!Command::Authenticate([0; 32]).is_privileged());
+}
+
+#[test]
+fn unauthenticated_legacy_restart_is_refused() {
+    let command = parse_legacy(&[0]).unwrap();
+    assert_eq!(command, Command::Restart);
+
+    // Paired device, client without a session
+    let result = command.authorize(false, || Ok(true)).map(|()| Reply::Ok);
+    assert_eq!(result, Err(CommandError::Unauthorized));
+    assert!(!command.restarts_after(&result));
+
+    assert!(command.authorize(true, || Ok(true)).is_ok());
+    assert!(command.authorize(false, || Ok(false)).is_ok());
+    assert!(command.restarts_after(&Ok(Reply::Ok)));
+    assert!(!Command::Hello.restarts_after(&Ok(Reply::Ok)));
+}
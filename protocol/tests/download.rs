@@ -0,0 +1,337 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use esp_pulser_protocol::*;
+
+/// How the test server misbehaves on one connection.
+#[derive(Debug, Clone, Copy)]
+enum Fault {
+    None,
+    /// Close the connection after sending this many body bytes
+    CutAfter(usize),
+    /// Send this many body bytes, then go quiet for a while
+    Stall(usize),
+    Status(u16),
+    /// Answer range requests with the whole file
+    IgnoreRange,
+}
+
+struct Server {
+    addr: SocketAddr,
+    /// `Range` header of every request received so far
+    ranges: Arc<Mutex<Vec<Option<String>>>>,
+}
+
+/// Serve `file` on a local port, applying `faults` to the connections in order.
+fn serve(file: Vec<u8>, faults: Vec<Fault>) -> Server {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let ranges = Arc::new(Mutex::new(Vec::new()));
+
+    let ranges_clone = ranges.clone();
+    let file = Arc::new(file);
+    thread::spawn(move || {
+        for (i, stream) in listener.incoming().enumerate() {
+            let fault = faults.get(i).copied().unwrap_or(Fault::None);
+            let file = file.clone();
+            let ranges = ranges_clone.clone();
+            // A stalled connection must not hold up the retry
+            thread::spawn(move || respond(stream.unwrap(), &file, fault, &ranges));
+        }
+    });
+    Server { addr, ranges }
+}
+
+fn respond(
+    mut stream: TcpStream,
+    file: &[u8],
+    fault: Fault,
+    ranges: &Mutex<Vec<Option<String>>>,
+) -> io::Result<()> {
+    let mut range = None;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(": ") {
+            if name.eq_ignore_ascii_case("range") {
+                range = Some(value.to_string());
+            }
+        }
+    }
+    ranges.lock().unwrap().push(range.clone());
+
+    if let Fault::Status(status) = fault {
+        return write!(
+            stream,
+            "HTTP/1.1 {status} Error\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        );
+    }
+
+    let offset = match (&range, fault) {
+        (Some(range), f) if !matches!(f, Fault::IgnoreRange) => range
+            .trim_start_matches("bytes=")
+            .trim_end_matches('-')
+            .parse()
+            .unwrap(),
+        _ => 0,
+    };
+    let body = &file[offset..];
+    if offset > 0 {
+        write!(
+            stream,
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\n",
+            offset,
+            file.len() - 1,
+            file.len()
+        )?;
+    } else {
+        write!(stream, "HTTP/1.1 200 OK\r\n")?;
+    }
+    write!(
+        stream,
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    )?;
+
+    match fault {
+        Fault::CutAfter(n) => stream.write_all(&body[..n.min(body.len())]),
+        Fault::Stall(n) => {
+            stream.write_all(&body[..n.min(body.len())])?;
+            thread::sleep(Duration::from_millis(500));
+            Ok(())
+        }
+        _ => stream.write_all(body),
+    }
+}
+
+/// Plain HTTP/1.1 client, one connection per request.
+struct TcpClient {
+    addr: SocketAddr,
+    timeout: Duration,
+    body: Option<BufReader<TcpStream>>,
+}
+
+fn io_error(e: io::Error) -> DownloadError {
+    match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => DownloadError::Timeout,
+        _ => DownloadError::Interrupted(e.to_string()),
+    }
+}
+
+impl HttpClient for TcpClient {
+    fn get(&mut self, url: &str, offset: usize) -> Result<ResponseHead, DownloadError> {
+        self.body = None;
+        let mut stream = TcpStream::connect_timeout(&self.addr, self.timeout)
+            .map_err(|e| DownloadError::Connect(e.to_string()))?;
+        stream.set_read_timeout(Some(self.timeout)).unwrap();
+
+        write!(stream, "GET {url} HTTP/1.1\r\nConnection: close\r\n").map_err(io_error)?;
+        if offset > 0 {
+            write!(stream, "Range: bytes={offset}-\r\n").map_err(io_error)?;
+        }
+        write!(stream, "\r\n").map_err(io_error)?;
+
+        let mut reader = BufReader::new(stream);
+        let mut head = ResponseHead::default();
+        let mut line = String::new();
+        reader.read_line(&mut line).map_err(io_error)?;
+        head.status = line
+            .split(' ')
+            .nth(1)
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| DownloadError::Interrupted("no status line".into()))?;
+        loop {
+            line.clear();
+            reader.read_line(&mut line).map_err(io_error)?;
+            let Some((name, value)) = line.trim_end().split_once(": ") else {
+                break;
+            };
+            match name.to_ascii_lowercase().as_str() {
+                "content-length" => head.content_length = value.parse().ok(),
+                "content-range" => head.content_range = Some(value.to_string()),
+                _ => (),
+            }
+        }
+        self.body = Some(reader);
+        Ok(head)
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, DownloadError> {
+        self.body.as_mut().unwrap().read(buf).map_err(io_error)
+    }
+}
+
+fn firmware() -> Vec<u8> {
+    (0..10_000).map(|i| (i * 7 % 251) as u8).collect()
+}
+
+fn config() -> DownloadConfig {
+    DownloadConfig {
+        chunk_len: 512,
+        timeout: Duration::from_millis(100),
+        max_attempts: 3,
+        initial_backoff: Duration::from_millis(1),
+        max_backoff: Duration::from_millis(4),
+    }
+}
+
+fn fetch(server: &Server) -> Result<Vec<u8>, DownloadError> {
+    let mut client = TcpClient {
+        addr: server.addr,
+        timeout: config().timeout,
+        body: None,
+    };
+    let mut data = Vec::new();
+    let len = download(&mut client, "/esp-pulser", &config(), &mut data)?;
+    assert_eq!(len, data.len());
+    Ok(data)
+}
+
+fn ranges(server: &Server) -> Vec<Option<String>> {
+    server.ranges.lock().unwrap().clone()
+}
+
+fn range(value: &str) -> Option<String> {
+    Some(value.to_string())
+}
+
+#[test]
+fn downloads_in_one_go() {
+    let server = serve(firmware(), vec![]);
+    assert_eq!(fetch(&server).unwrap(), firmware());
+    assert_eq!(ranges(&server), [None]);
+}
+
+#[test]
+fn resumes_after_disconnects() {
+    let server = serve(
+        firmware(),
+        vec![
+            Fault::CutAfter(1000),
+            Fault::CutAfter(0),
+            Fault::CutAfter(3000),
+        ],
+    );
+    assert_eq!(fetch(&server).unwrap(), firmware());
+    assert_eq!(
+        ranges(&server),
+        [
+            None,
+            range("bytes=1000-"),
+            range("bytes=1000-"),
+            range("bytes=4000-")
+        ]
+    );
+}
+
+#[test]
+fn resumes_after_timeouts() {
+    let server = serve(firmware(), vec![Fault::Stall(2048)]);
+    assert_eq!(fetch(&server).unwrap(), firmware());
+    assert_eq!(ranges(&server), [None, range("bytes=2048-")]);
+}
+
+#[test]
+fn retries_server_errors() {
+    let server = serve(firmware(), vec![Fault::Status(503)]);
+    assert_eq!(fetch(&server).unwrap(), firmware());
+    assert_eq!(ranges(&server).len(), 2);
+}
+
+#[test]
+fn fails_on_client_errors() {
+    let server = serve(firmware(), vec![Fault::Status(404)]);
+    assert_eq!(fetch(&server), Err(DownloadError::Status(404)));
+    assert_eq!(ranges(&server).len(), 1);
+}
+
+#[test]
+fn fails_without_range_support() {
+    let server = serve(firmware(), vec![Fault::CutAfter(100), Fault::IgnoreRange]);
+    assert_eq!(fetch(&server), Err(DownloadError::RangeNotSupported));
+}
+
+#[test]
+fn gives_up_without_progress() {
+    let server = serve(firmware(), vec![Fault::CutAfter(0); 10]);
+    match fetch(&server) {
+        Err(DownloadError::RetriesExhausted { attempts, last }) => {
+            assert_eq!(attempts, 3);
+            assert!(matches!(*last, DownloadError::Interrupted(_)));
+        }
+        other => panic!("unexpected {other:?}"),
+    }
+    assert_eq!(ranges(&server).len(), 3);
+}
+
+#[test]
+fn sink_can_stop_retries() {
+    struct Cancelling(Vec<u8>);
+
+    impl DownloadSink for Cancelling {
+        fn begin(&mut self, total: usize) -> Result<(), DownloadError> {
+            self.0.begin(total)
+        }
+
+        fn write(&mut self, data: &[u8]) -> Result<(), DownloadError> {
+            DownloadSink::write(&mut self.0, data)
+        }
+
+        fn retrying(&mut self, _: &DownloadError, _: Duration) -> Result<(), DownloadError> {
+            Err(DownloadError::Cancelled)
+        }
+    }
+
+    let server = serve(firmware(), vec![Fault::CutAfter(100)]);
+    let mut client = TcpClient {
+        addr: server.addr,
+        timeout: config().timeout,
+        body: None,
+    };
+    let mut sink = Cancelling(Vec::new());
+    assert_eq!(
+        download(&mut client, "/esp-pulser", &config(), &mut sink),
+        Err(DownloadError::Cancelled)
+    );
+    assert_eq!(sink.0, firmware()[..100]);
+}
+
+#[test]
+fn error_classification() {
+    assert!(DownloadError::Timeout.is_transient());
+    assert!(DownloadError::Connect("refused".into()).is_transient());
+    assert!(DownloadError::Status(503).is_transient());
+    assert!(DownloadError::Status(429).is_transient());
+    assert!(!DownloadError::Status(404).is_transient());
+    assert!(!DownloadError::RangeNotSupported.is_transient());
+    assert!(!DownloadError::Cancelled.is_transient());
+}
+
+#[test]
+fn backoff_doubles_up_to_limit() {
+    let config = DownloadConfig::default();
+    let backoffs: Vec<_> = (1..=7)
+        .map(|retry| config.backoff(retry).as_secs())
+        .collect();
+    assert_eq!(backoffs, [1, 2, 4, 8, 16, 16, 16]);
+    assert_eq!(config.backoff(100), config.max_backoff);
+}
+
+#[test]
+fn content_ranges() {
+    assert_eq!(
+        parse_content_range("bytes 1000-9999/10000"),
+        Some((1000, 9999, Some(10000)))
+    );
+    assert_eq!(parse_content_range("bytes 0-9/*"), Some((0, 9, None)));
+    assert_eq!(parse_content_range("items 0-9/10"), None);
+    assert_eq!(parse_content_range("bytes 0-9"), None);
+}
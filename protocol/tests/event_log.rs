@@ -0,0 +1,96 @@
+use esp_pulser_protocol::*;
+
+fn warn(log: &mut EventLog, timestamp_us: u64, message: &str) {
+    log.push(3, timestamp_us, Severity::Warning, "streaming", message);
+}
+
+fn messages(log: &EventLog) -> Vec<(u32, &str, u32)> {
+    log.entries()
+        .map(|entry| (entry.seq, entry.message.as_str(), entry.repeated))
+        .collect()
+}
+
+#[test]
+fn repeated_messages_are_counted() {
+    let mut log = EventLog::new();
+    warn(&mut log, 10, "Error sending data");
+    warn(&mut log, 20, "Error sending data");
+    warn(&mut log, 30, "Error sending data");
+    log.push(3, 40, Severity::Error, "streaming", "Error sending data");
+    warn(&mut log, 50, "Error sending data");
+
+    assert_eq!(
+        messages(&log),
+        [
+            (0, "Error sending data", 2),
+            (1, "Error sending data", 0),
+            (2, "Error sending data", 0),
+        ]
+    );
+    assert_eq!(log.entries().next().unwrap().timestamp_us, 10);
+}
+
+#[test]
+fn oldest_entries_are_dropped() {
+    let mut log = EventLog::new();
+    for i in 0..EVENT_LOG_CAPACITY as u64 + 10 {
+        warn(&mut log, i, &i.to_string());
+    }
+    assert_eq!(log.entries().count(), EVENT_LOG_CAPACITY);
+    assert_eq!(log.entries().next().unwrap().seq, 10);
+
+    // Paging resumes after the last entry received
+    let page = log.page(0);
+    assert_eq!(page.len(), EVENT_LOG_PAGE_LEN);
+    assert_eq!(page[0].seq, 10);
+    let page = log.page(page.last().unwrap().seq + 1);
+    assert_eq!(page[0].seq, 10 + EVENT_LOG_PAGE_LEN as u32);
+    assert!(log.page(EVENT_LOG_CAPACITY as u32 + 10).is_empty());
+}
+
+#[test]
+fn long_messages_are_cut_off() {
+    let mut log = EventLog::new();
+    let message = "é".repeat(EVENT_LOG_MESSAGE_MAX_LEN);
+    log.push(1, 0, Severity::Error, "a_very_long_module_name", &message);
+    let entry = log.entries().next().unwrap();
+    assert_eq!(entry.message.len(), EVENT_LOG_MESSAGE_MAX_LEN);
+    assert_eq!(entry.subsystem, "a_very_long_modu");
+}
+
+#[test]
+fn restore_and_clear() {
+    let mut stored = EventLog::new();
+    warn(&mut stored, 10, "first");
+    warn(&mut stored, 20, "second");
+    let stored = decode_event_log(&encode_event_log(&stored).unwrap()).unwrap();
+
+    // Logged before the boot count was read
+    let mut log = EventLog::new();
+    log.push(0, 5, Severity::Warning, "settings", "early");
+    log.restore(stored, 4);
+    assert_eq!(
+        messages(&log),
+        [(0, "first", 0), (1, "second", 0), (2, "early", 0)]
+    );
+    assert_eq!(log.entries().last().unwrap().boot_count, 4);
+
+    log.clear();
+    assert_eq!(log.entries().count(), 0);
+    warn(&mut log, 30, "after");
+    assert_eq!(messages(&log), [(3, "after", 0)]);
+}
+
+#[test]
+fn stored_log() {
+    let bytes = encode_event_log(&EventLog::new()).unwrap();
+    assert_eq!(bytes, [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    assert!(matches!(
+        decode_event_log(&[2, 0]),
+        Err(EventLogError::UnknownVersion(2))
+    ));
+    assert!(matches!(
+        decode_event_log(&[1, 0, 5]),
+        Err(EventLogError::Corrupt(_))
+    ));
+}
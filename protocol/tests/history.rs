@@ -0,0 +1,116 @@
+use esp_pulser_protocol::*;
+
+const SAMPLE_RATE: f32 = 400.0;
+const PERIOD_US: u64 = 2500;
+
+fn sample(index: u64, value: f32) -> Sample {
+    Sample {
+        index,
+        timestamp_us: index * PERIOD_US,
+        value,
+    }
+}
+
+/// Stream `count` samples the way the sampling loop does.
+fn stream(history: &mut History, from: u64, count: u64) {
+    for index in from..from + count {
+        history.record(&Packet::RawHeartRate(sample(index, index as f32)));
+        history.record(&Packet::HeartRate(sample(index, -(index as f32))));
+        history.record(&Packet::Bpm(sample(index, 60.0)));
+    }
+}
+
+fn read_all(history: &History) -> Vec<Packet> {
+    let mut packets = Vec::new();
+    let mut next = history.start();
+    while next < history.end() {
+        let (chunk, n) = history.read(next, 7);
+        packets.extend(chunk);
+        next = n;
+    }
+    packets
+}
+
+#[test]
+fn samples_are_decimated() {
+    let mut history = History::new(SAMPLE_RATE);
+    assert_eq!(history.sample_divider(), 4);
+    stream(&mut history, 1, 8);
+
+    let packets = read_all(&history);
+    assert_eq!(
+        packets,
+        [
+            // BPM is kept only when it changes
+            Packet::Bpm(sample(1, 60.0)),
+            Packet::RawHeartRate(sample(4, 4.0)),
+            Packet::HeartRate(sample(4, -4.0)),
+            Packet::RawHeartRate(sample(8, 8.0)),
+            Packet::HeartRate(sample(8, -8.0)),
+        ]
+    );
+}
+
+#[test]
+fn beats_and_events_keep_their_timestamps() {
+    let mut history = History::new(SAMPLE_RATE);
+    let beat = BeatEvent {
+        sample_index: 2,
+        timestamp_us: 3 * PERIOD_US,
+        ibi_ms: 800.0,
+        amplitude: 900.0,
+        confidence: 0.5,
+    };
+    let event = StampedEvent {
+        sample_index: 4,
+        timestamp_us: 4 * PERIOD_US + 100,
+        event: SessionEvent::FingerLost,
+    };
+    history.record(&Packet::Beat(beat));
+    history.record(&Packet::Event(event));
+    history.record(&Packet::Update(UpdateStatus::default()));
+
+    assert_eq!(
+        read_all(&history),
+        [Packet::Beat(beat), Packet::Event(event)]
+    );
+}
+
+#[test]
+fn old_entries_are_evicted() {
+    let mut history = History::new(SAMPLE_RATE);
+    let per_sec = SAMPLE_RATE as u64;
+    stream(&mut history, 0, (HISTORY_SECS + 2) * per_sec + 1);
+
+    let packets = read_all(&history);
+    let Packet::RawHeartRate(first) = packets[0] else {
+        panic!("{:?}", packets[0]);
+    };
+    // Everything within the window is kept, BPM is gone with its sample
+    assert_eq!(first.index, 2 * per_sec);
+    assert_eq!(
+        packets.len() as u64,
+        2 * (HISTORY_SECS * per_sec / history.sample_divider() + 1)
+    );
+    assert!(history.start() > 0);
+}
+
+#[test]
+fn reads_continue_across_eviction() {
+    let mut history = History::new(SAMPLE_RATE);
+    stream(&mut history, 0, 400);
+    let (packets, next) = history.read(history.start(), 10);
+    // The first BPM and nine samples
+    assert_eq!(packets.len(), 1 + 2 * 9);
+    assert_eq!(next, history.start() + 10);
+
+    // The cursor fell out of the window, reading resumes at the oldest entry kept
+    stream(&mut history, 400, HISTORY_SECS * 400 + 1);
+    let (packets, next) = history.read(next, 1);
+    assert_eq!(packets[0], Packet::RawHeartRate(sample(400, 400.0)));
+    assert_eq!(next, history.start() + 1);
+
+    let (packets, next) = history.read(history.end(), 10);
+    assert!(packets.is_empty());
+    assert_eq!(next, history.end());
+}
@@ -0,0 +1,70 @@
+use esp_pulser_protocol::*;
+
+const SIGNING_KEY: [u8; 32] = [7; 32];
+
+fn public_key() -> [u8; 32] {
+    ed25519_dalek::SigningKey::from_bytes(&SIGNING_KEY)
+        .verifying_key()
+        .to_bytes()
+}
+
+#[test]
+fn digest_is_sha256() {
+    assert_eq!(
+        format_image_digest(&image_digest(b"esp-pulser"), "esp-pulser"),
+        "3ed90a1fd28ae5a451690f8efb1965d6719fa59f702331e85b28c84dab333352  esp-pulser\n"
+    );
+}
+
+#[test]
+fn digest_round_trip() {
+    let digest = image_digest(b"esp-pulser");
+    let text = format_image_digest(&digest, "esp-pulser");
+    assert_eq!(parse_image_digest(&text), Some(digest));
+    assert_eq!(parse_image_digest(text.trim_end()), Some(digest));
+
+    assert_eq!(parse_image_digest(""), None);
+    assert_eq!(parse_image_digest("3ed90a1f  esp-pulser"), None);
+    assert_eq!(parse_image_digest(&text.replace('3', "x")), None);
+}
+
+#[test]
+fn hex_keys() {
+    const KEY: Option<[u8; 4]> = decode_hex("00ff7Ea1\n");
+    assert_eq!(KEY, Some([0x00, 0xFF, 0x7E, 0xA1]));
+    assert_eq!(decode_hex::<4>("00ff7ea1  "), KEY);
+
+    assert_eq!(decode_hex::<4>("00ff7e"), None);
+    assert_eq!(decode_hex::<4>("00ff7ea1b2"), None);
+    assert_eq!(decode_hex::<4>(" 00ff7ea1"), None);
+    assert_eq!(decode_hex::<4>("00fg7ea1"), None);
+    assert_eq!(decode_hex::<0>(""), Some([]));
+}
+
+#[test]
+fn signature_verification() {
+    let digest = image_digest(b"esp-pulser");
+    let signature = sign_image_digest(&SIGNING_KEY, &digest);
+    assert!(verify_image_signature(&public_key(), &digest, &signature));
+
+    let tampered = image_digest(b"esp-pulsar");
+    assert!(!verify_image_signature(
+        &public_key(),
+        &tampered,
+        &signature
+    ));
+
+    let mut forged = signature;
+    forged[10] ^= 1;
+    assert!(!verify_image_signature(&public_key(), &digest, &forged));
+
+    let other_key = sign_image_digest(&[8; 32], &digest);
+    assert!(!verify_image_signature(&public_key(), &digest, &other_key));
+
+    assert!(!verify_image_signature(&public_key(), &digest, &[]));
+    assert!(!verify_image_signature(
+        &public_key(),
+        &digest,
+        &signature[..63]
+    ));
+}
@@ -0,0 +1,140 @@
+use esp_pulser_protocol::*;
+
+const HEADER: RecordingHeader = RecordingHeader {
+    firmware_version: [0, 6, 4],
+    sample_rate: 400.0,
+    started_us: 1_000_000,
+};
+
+fn sample(index: u64, value: f32) -> Sample {
+    Sample {
+        index,
+        timestamp_us: HEADER.started_us + index * 2500,
+        value,
+    }
+}
+
+fn session() -> Vec<Record> {
+    let mut records = vec![Record::Event {
+        timestamp_us: 1_000_100,
+        event: SessionEvent::FingerDetected,
+    }];
+    records.extend((1..=100).map(|i| Record::Sample(sample(i, 120_000.0 + (i * 37 % 500) as f32))));
+    records.push(Record::Beat(BeatEvent {
+        sample_index: 100,
+        // Beats are stamped at their peak, which lies in the past
+        timestamp_us: 1_200_000,
+        ibi_ms: 812.5,
+        amplitude: 950.0,
+        confidence: 0.75,
+    }));
+    // A gap in the samples
+    records.push(Record::Sample(sample(250, 90_000.0)));
+    records.push(Record::Event {
+        timestamp_us: 1_700_000,
+        event: SessionEvent::Disconnected,
+    });
+    records
+}
+
+fn encode_session(records: &[Record]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut encoder = RecordingEncoder::new(&HEADER, &mut out);
+    for record in records {
+        match record {
+            Record::Sample(sample) => encoder.sample(&mut out, sample),
+            Record::Beat(beat) => encoder.beat(&mut out, beat),
+            Record::Event {
+                timestamp_us,
+                event,
+            } => encoder.event(&mut out, *timestamp_us, *event),
+        }
+    }
+    out
+}
+
+#[test]
+fn header() {
+    let bytes = encode_session(&[]);
+    assert_eq!(
+        bytes,
+        [
+            b'P', b'L', b'S', b'R', 1, 0, 6, 4, 0x00, 0x00, 0xC8, 0x43, 0x40, 0x42, 0x0F, 0, 0, 0,
+            0, 0,
+        ]
+    );
+    let decoder = RecordingDecoder::new(&bytes).unwrap();
+    assert_eq!(decoder.header, HEADER);
+    assert_eq!(decoder.count(), 0);
+}
+
+#[test]
+fn round_trip() {
+    let records = session();
+    let bytes = encode_session(&records);
+    let decoded: Result<Vec<_>, _> = RecordingDecoder::new(&bytes).unwrap().collect();
+    assert_eq!(decoded.unwrap(), records);
+}
+
+#[test]
+fn samples_are_compact() {
+    let samples: Vec<_> = (1..=400)
+        .map(|i| Record::Sample(sample(i, 120_000.0 + (i % 50) as f32 * 10.0)))
+        .collect();
+    let bytes = encode_session(&samples);
+    // Tag, 2500 us and a small value change
+    assert!(
+        bytes.len() - RECORDING_HEADER_LEN <= 400 * 5,
+        "{}",
+        bytes.len()
+    );
+}
+
+#[test]
+fn sample_encoding() {
+    let mut out = Vec::new();
+    let mut encoder = RecordingEncoder::new(&HEADER, &mut out);
+    out.clear();
+    encoder.sample(&mut out, &sample(1, 100.0));
+    encoder.sample(&mut out, &sample(2, 99.0));
+    assert_eq!(out, [1, 2, 0x88, 0x27, 0xC8, 0x01, 0, 0x88, 0x27, 0x01]);
+}
+
+#[test]
+fn truncated_recordings_end_early() {
+    let records = session();
+    let bytes = encode_session(&records);
+    let cut = &bytes[..bytes.len() - 1];
+    let decoded: Vec<_> = RecordingDecoder::new(cut).unwrap().collect();
+    assert_eq!(decoded.len(), records.len());
+    assert_eq!(decoded.last(), Some(&Err(RecordingError::Truncated)));
+    assert!(decoded[..records.len() - 1]
+        .iter()
+        .zip(&records)
+        .all(|(decoded, record)| decoded.as_ref() == Ok(record)));
+
+    assert!(matches!(
+        RecordingDecoder::new(&bytes[..10]),
+        Err(RecordingError::Truncated)
+    ));
+}
+
+#[test]
+fn invalid_recordings() {
+    let mut bytes = encode_session(&session());
+    assert!(matches!(
+        RecordingDecoder::new(b"PK\x03\x04"),
+        Err(RecordingError::BadMagic)
+    ));
+
+    let mut future = bytes.clone();
+    future[4] = 9;
+    assert!(matches!(
+        RecordingDecoder::new(&future),
+        Err(RecordingError::UnknownFormat(9))
+    ));
+
+    bytes.push(0x7F);
+    let last = RecordingDecoder::new(&bytes).unwrap().last();
+    assert_eq!(last, Some(Err(RecordingError::UnknownTag(0x7F))));
+}
@@ -0,0 +1,144 @@
+use esp_pulser_protocol::*;
+
+fn custom() -> Settings {
+    Settings {
+        sensor: SensorSettings { led_amplitude: 60 },
+        detector: DetectorSettings {
+            finger_threshold: 80_000.0,
+            finger_cooldown_ms: 1000,
+            edge_threshold: -1500.0,
+            min_ibi_ms: 350,
+        },
+        haptic: HapticSettings {
+            enabled: false,
+            amplitude: 120,
+            effect: HapticEffect::DoubleClick,
+        },
+        network: NetworkSettings {
+            release_url: Some("http://10.0.0.2/releases".into()),
+            download_timeout_secs: 30,
+            download_attempts: 8,
+            download_max_backoff_secs: 60,
+        },
+        display: DisplaySettings {
+            enabled: false,
+            backlight_secs: 0,
+        },
+    }
+}
+
+#[test]
+fn blob_round_trip() {
+    let bytes = encode_settings(&Settings::default()).unwrap();
+    assert_eq!(bytes[..3], [1, 0, 35]);
+    assert_eq!(decode_settings(&bytes), Ok(Settings::default()));
+
+    let bytes = encode_settings(&custom()).unwrap();
+    assert_eq!(decode_settings(&bytes), Ok(custom()));
+}
+
+#[test]
+fn corrupt_blobs_are_rejected() {
+    let bytes = encode_settings(&custom()).unwrap();
+    assert_eq!(decode_settings(&[]), Err(SettingsError::Empty));
+    assert!(matches!(
+        decode_settings(&bytes[..1]),
+        Err(SettingsError::Corrupt(_))
+    ));
+    assert!(matches!(
+        decode_settings(&bytes[..bytes.len() - 4]),
+        Err(SettingsError::Corrupt(_))
+    ));
+
+    let mut future = bytes.clone();
+    future[0] = 9;
+    assert_eq!(
+        decode_settings(&future),
+        Err(SettingsError::UnknownVersion(9))
+    );
+
+    let mut invalid = custom();
+    invalid.detector.edge_threshold = f32::NAN;
+    let bytes = encode_settings(&invalid).unwrap();
+    assert!(matches!(
+        decode_settings(&bytes),
+        Err(SettingsError::Invalid {
+            field: "detector.edge_threshold",
+            ..
+        })
+    ));
+}
+
+#[test]
+fn download_config() {
+    assert_eq!(
+        NetworkSettings::default().download_config(),
+        DownloadConfig::default()
+    );
+
+    let config = custom().network.download_config();
+    assert_eq!(config.timeout, std::time::Duration::from_secs(30));
+    assert_eq!(config.max_attempts, 8);
+    assert_eq!(config.max_backoff, std::time::Duration::from_secs(60));
+}
+
+#[test]
+fn legacy_keys_are_migrated() {
+    assert_eq!(
+        Settings::from_legacy(LegacySettings::default()),
+        Settings::default()
+    );
+
+    let settings = Settings::from_legacy(LegacySettings {
+        led_amplitude: Some(60),
+        haptic_amplitude: Some(120),
+    });
+    assert_eq!(settings.sensor.led_amplitude, 60);
+    assert_eq!(settings.haptic.amplitude, 120);
+    assert!(settings.haptic.enabled);
+    assert_eq!(settings.network, NetworkSettings::default());
+    assert_eq!(settings.detector, DetectorSettings::default());
+}
+
+#[test]
+fn validation() {
+    assert_eq!(Settings::default().validate(), Ok(()));
+    assert_eq!(custom().validate(), Ok(()));
+
+    let field = |settings: Settings| match settings.validate() {
+        Err(SettingsError::Invalid { field, .. }) => field,
+        other => panic!("unexpected {other:?}"),
+    };
+
+    let mut settings = custom();
+    settings.detector.finger_threshold = 0.0;
+    assert_eq!(field(settings), "detector.finger_threshold");
+
+    let mut settings = custom();
+    settings.detector.edge_threshold = 10.0;
+    assert_eq!(field(settings), "detector.edge_threshold");
+
+    let mut settings = custom();
+    settings.detector.min_ibi_ms = 100;
+    assert_eq!(field(settings), "detector.min_ibi_ms");
+
+    let mut settings = custom();
+    settings.network.release_url = Some("ftp://example.com".into());
+    assert_eq!(field(settings), "network.release_url");
+
+    let mut settings = custom();
+    settings.network.release_url = Some(format!("https://{}", "a".repeat(RELEASE_URL_MAX_LEN)));
+    assert_eq!(field(settings), "network.release_url");
+
+    let mut settings = custom();
+    settings.network.download_timeout_secs = 0;
+    assert_eq!(field(settings), "network.download_timeout_secs");
+
+    let mut settings = custom();
+    settings.network.download_attempts = 0;
+    assert_eq!(field(settings), "network.download_attempts");
+
+    let mut settings = custom();
+    settings.network.download_max_backoff_secs = 3600;
+    assert_eq!(field(settings), "network.download_max_backoff_secs");
+}
@@ -0,0 +1,70 @@
+use esp_pulser_protocol::*;
+
+#[test]
+fn versions() {
+    assert_eq!(parse_version("0.6.4"), Some([0, 6, 4]));
+    assert_eq!(parse_version("v1.2.3\n"), Some([1, 2, 3]));
+    assert_eq!(parse_version("0.7.0-rc.1"), Some([0, 7, 0]));
+    assert_eq!(parse_version("0.7.0+build5"), Some([0, 7, 0]));
+    assert_eq!(parse_version("0.7"), None);
+    assert_eq!(parse_version("0.7.0.1"), None);
+    assert_eq!(parse_version("0.256.0"), None);
+    assert_eq!(parse_version("latest"), None);
+    assert_eq!(format_version([0, 10, 2]), "0.10.2");
+}
+
+#[test]
+fn only_newer_images_are_installed() {
+    let running = [0, 6, 4];
+    assert_eq!(check_image_version(running, "0.6.5", false), Ok(()));
+    assert_eq!(check_image_version(running, "0.10.0", false), Ok(()));
+    assert_eq!(
+        check_image_version(running, "0.6.4", false),
+        Err(VersionError::SameVersion(running))
+    );
+    assert_eq!(
+        check_image_version(running, "0.5.9", false),
+        Err(VersionError::Downgrade {
+            running,
+            incoming: [0, 5, 9]
+        })
+    );
+    assert_eq!(
+        check_image_version(running, "1", false),
+        Err(VersionError::Invalid("1".into()))
+    );
+    assert_eq!(
+        check_image_version(running, "0.6.4", false)
+            .unwrap_err()
+            .to_string(),
+        "firmware 0.6.4 is already installed"
+    );
+}
+
+#[test]
+fn forced_images_are_always_installed() {
+    let running = [0, 6, 4];
+    assert_eq!(check_image_version(running, "0.6.4", true), Ok(()));
+    assert_eq!(check_image_version(running, "0.5.0", true), Ok(()));
+    assert_eq!(check_image_version(running, "unknown", true), Ok(()));
+}
+
+#[test]
+fn release_manifest() {
+    let manifest = ReleaseManifest {
+        version: [0, 7, 0],
+        image: "https://example.com/v0.7.0/esp-pulser".into(),
+    };
+    let text = format_release_manifest(&manifest);
+    assert_eq!(
+        text,
+        "version=0.7.0\nimage=https://example.com/v0.7.0/esp-pulser\n"
+    );
+    assert_eq!(parse_release_manifest(&text), Some(manifest.clone()));
+
+    let annotated = format!("# esp-pulser release\n\nchannel=stable\n{text}");
+    assert_eq!(parse_release_manifest(&annotated), Some(manifest));
+
+    assert_eq!(parse_release_manifest("version=0.7.0\n"), None);
+    assert_eq!(parse_release_manifest("version=next\nimage=x\n"), None);
+}
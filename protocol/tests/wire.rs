@@ -0,0 +1,883 @@
+//! Byte-for-byte pins of the wire encoding. If one of these fails, the change breaks
+//! compatibility with devices and GUI builds in the field.
+
+use std::net::{Ipv4Addr, SocketAddr};
+
+use esp_pulser_protocol::*;
+
+#[test]
+fn status_packet() {
+    let status = Status {
+        version: [0, 6, 4],
+        connected: true,
+        display_ok: false,
+        haptic_ok: true,
+        heart_ok: true,
+        led_amplitude: 35,
+        haptic_amplitude: 255,
+        running_slot: "app1".into(),
+        reset_reason: ResetReason::Software,
+        boot_count: 7,
+        core_dump: true,
+    };
+    let bytes = encode(&Packet::Status(status.clone())).unwrap();
+    // Legacy GUI builds only know the fields up to the haptic amplitude
+    assert_eq!(bytes[..13], [0, 0, 0, 0, 0, 6, 4, 1, 0, 1, 1, 35, 255]);
+    assert_eq!(
+        bytes[13..],
+        [4, 0, 0, 0, 0, 0, 0, 0, b'a', b'p', b'p', b'1', 3, 0, 0, 0, 7, 0, 0, 0, 1]
+    );
+    assert_eq!(decode::<Packet>(&bytes).unwrap(), Packet::Status(status));
+}
+
+#[test]
+fn sample_packets() {
+    let sample = Sample {
+        index: 1,
+        timestamp_us: 2500,
+        value: 1.5,
+    };
+    let payload = [
+        1, 0, 0, 0, 0, 0, 0, 0, 0xC4, 0x09, 0, 0, 0, 0, 0, 0, 0, 0, 0xC0, 0x3F,
+    ];
+
+    for (tag, packet) in [
+        (1, Packet::RawHeartRate(sample)),
+        (2, Packet::Bpm(sample)),
+        (3, Packet::HeartRate(sample)),
+    ] {
+        let bytes = encode(&packet).unwrap();
+        assert_eq!(bytes[..4], [tag, 0, 0, 0]);
+        assert_eq!(bytes[4..], payload);
+        assert_eq!(decode::<Packet>(&bytes).unwrap(), packet);
+    }
+}
+
+#[test]
+fn beat_packet() {
+    let packet = Packet::Beat(BeatEvent {
+        sample_index: 10,
+        timestamp_us: 1000,
+        ibi_ms: 800.0,
+        amplitude: 0.5,
+        confidence: 1.0,
+    });
+    let bytes = encode(&packet).unwrap();
+    assert_eq!(
+        bytes,
+        [
+            4, 0, 0, 0, 10, 0, 0, 0, 0, 0, 0, 0, 0xE8, 0x03, 0, 0, 0, 0, 0, 0, 0, 0, 0x48, 0x44, 0,
+            0, 0, 0x3F, 0, 0, 0x80, 0x3F,
+        ]
+    );
+    assert_eq!(decode::<Packet>(&bytes).unwrap(), packet);
+}
+
+#[test]
+fn update_packets() {
+    let cases = [
+        (
+            Packet::Update(UpdateStatus {
+                state: UpdateState::Downloading,
+                progress: 42,
+            }),
+            vec![5, 0, 0, 0, 1, 0, 0, 0, 42],
+        ),
+        (
+            Packet::Update(UpdateStatus {
+                state: UpdateState::Failed("x".into()),
+                progress: 100,
+            }),
+            vec![5, 0, 0, 0, 5, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, b'x', 100],
+        ),
+        (
+            Packet::Update(UpdateStatus {
+                state: UpdateState::Cancelled,
+                progress: 7,
+            }),
+            vec![5, 0, 0, 0, 6, 0, 0, 0, 7],
+        ),
+        (
+            Packet::Update(UpdateStatus {
+                state: UpdateState::UpToDate,
+                progress: 1,
+            }),
+            vec![5, 0, 0, 0, 7, 0, 0, 0, 1],
+        ),
+    ];
+
+    for (packet, bytes) in cases {
+        assert_eq!(encode(&packet).unwrap(), bytes, "{packet:?}");
+        assert_eq!(decode::<Packet>(&bytes).unwrap(), packet);
+    }
+}
+
+#[test]
+fn event_packet() {
+    let packet = Packet::Event(StampedEvent {
+        sample_index: 10,
+        timestamp_us: 1000,
+        event: SessionEvent::FingerLost,
+    });
+    let bytes = encode(&packet).unwrap();
+    assert_eq!(
+        bytes,
+        [6, 0, 0, 0, 10, 0, 0, 0, 0, 0, 0, 0, 0xE8, 0x03, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0,]
+    );
+    assert_eq!(decode::<Packet>(&bytes).unwrap(), packet);
+}
+
+#[test]
+fn requests() {
+    let cases = [
+        (
+            Request {
+                id: 1,
+                command: Command::Hello,
+            },
+            vec![1, 0, 0, 0, 0, 0, 0, 0],
+        ),
+        (
+            Request {
+                id: 2,
+                command: Command::Restart,
+            },
+            vec![2, 0, 0, 0, 1, 0, 0, 0],
+        ),
+        (
+            Request {
+                id: 3,
+                command: Command::Update("0.6.4".into()),
+            },
+            vec![
+                3, 0, 0, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, b'0', b'.', b'6', b'.', b'4',
+            ],
+        ),
+        (
+            Request {
+                id: 4,
+                command: Command::SetLedAmplitude(35),
+            },
+            vec![4, 0, 0, 0, 3, 0, 0, 0, 35],
+        ),
+        (
+            Request {
+                id: 5,
+                command: Command::ConnectUdp(0x1234),
+            },
+            vec![5, 0, 0, 0, 4, 0, 0, 0, 0x34, 0x12],
+        ),
+        (
+            Request {
+                id: 6,
+                command: Command::SetHapticAmplitude(255),
+            },
+            vec![6, 0, 0, 0, 5, 0, 0, 0, 255],
+        ),
+        (
+            Request {
+                id: 7,
+                command: Command::GetConfig,
+            },
+            vec![7, 0, 0, 0, 6, 0, 0, 0],
+        ),
+        (
+            Request {
+                id: 8,
+                command: Command::GetSensorHealth,
+            },
+            vec![8, 0, 0, 0, 10, 0, 0, 0],
+        ),
+        (
+            Request {
+                id: 9,
+                command: Command::Subscribe(Subscription {
+                    port: 3334,
+                    streams: Streams {
+                        status: true,
+                        raw: false,
+                        filtered: true,
+                        bpm: true,
+                        beats: true,
+                    },
+                    sample_rate_hz: 100,
+                }),
+            },
+            vec![9, 0, 0, 0, 11, 0, 0, 0, 0x06, 0x0D, 1, 0, 1, 1, 1, 100, 0],
+        ),
+        (
+            Request {
+                id: 10,
+                command: Command::Unsubscribe(3334),
+            },
+            vec![10, 0, 0, 0, 12, 0, 0, 0, 0x06, 0x0D],
+        ),
+        (
+            Request {
+                id: 11,
+                command: Command::AuthChallenge,
+            },
+            vec![11, 0, 0, 0, 15, 0, 0, 0],
+        ),
+        (
+            Request {
+                id: 12,
+                command: Command::Authenticate([7; 32]),
+            },
+            [vec![12, 0, 0, 0, 16, 0, 0, 0], vec![7; 32]].concat(),
+        ),
+        (
+            Request {
+                id: 13,
+                command: Command::UploadBegin {
+                    size: 0x010000,
+                    sha256: [1; 32],
+                    signature: vec![2; 64],
+                    force: true,
+                },
+            },
+            [
+                vec![13, 0, 0, 0, 18, 0, 0, 0, 0, 0, 1, 0],
+                vec![1; 32],
+                vec![64, 0, 0, 0, 0, 0, 0, 0],
+                vec![2; 64],
+                vec![1],
+            ]
+            .concat(),
+        ),
+        (
+            Request {
+                id: 14,
+                command: Command::UploadChunk {
+                    offset: 3072,
+                    data: vec![0xE9, 0x03],
+                },
+            },
+            vec![
+                14, 0, 0, 0, 19, 0, 0, 0, 0x00, 0x0C, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0xE9, 0x03,
+            ],
+        ),
+        (
+            Request {
+                id: 15,
+                command: Command::UploadFinish,
+            },
+            vec![15, 0, 0, 0, 20, 0, 0, 0],
+        ),
+        (
+            Request {
+                id: 16,
+                command: Command::GetUpdateOutcome,
+            },
+            vec![16, 0, 0, 0, 22, 0, 0, 0],
+        ),
+        (
+            Request {
+                id: 17,
+                command: Command::CancelUpdate,
+            },
+            vec![17, 0, 0, 0, 23, 0, 0, 0],
+        ),
+        (
+            Request {
+                id: 18,
+                command: Command::GetUpdateStatus,
+            },
+            vec![18, 0, 0, 0, 24, 0, 0, 0],
+        ),
+        (
+            Request {
+                id: 19,
+                command: Command::ForceUpdate("0.6.4".into()),
+            },
+            vec![
+                19, 0, 0, 0, 25, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, b'0', b'.', b'6', b'.', b'4',
+            ],
+        ),
+        (
+            Request {
+                id: 20,
+                command: Command::CheckForUpdate,
+            },
+            vec![20, 0, 0, 0, 26, 0, 0, 0],
+        ),
+        (
+            Request {
+                id: 21,
+                command: Command::SetReleaseUrl("h".into()),
+            },
+            vec![21, 0, 0, 0, 27, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, b'h'],
+        ),
+        (
+            Request {
+                id: 22,
+                command: Command::GetBootInfo,
+            },
+            vec![22, 0, 0, 0, 28, 0, 0, 0],
+        ),
+        (
+            Request {
+                id: 23,
+                command: Command::SwitchBootSlot,
+            },
+            vec![23, 0, 0, 0, 29, 0, 0, 0],
+        ),
+        (
+            Request {
+                id: 24,
+                command: Command::ExportSettings,
+            },
+            vec![24, 0, 0, 0, 30, 0, 0, 0],
+        ),
+        (
+            Request {
+                id: 25,
+                command: Command::ImportSettings(Settings::default()),
+            },
+            [vec![25, 0, 0, 0, 31, 0, 0, 0], default_settings_bytes()].concat(),
+        ),
+        (
+            Request {
+                id: 26,
+                command: Command::FactoryReset,
+            },
+            vec![26, 0, 0, 0, 32, 0, 0, 0],
+        ),
+        (
+            Request {
+                id: 27,
+                command: Command::StartRecording,
+            },
+            vec![27, 0, 0, 0, 33, 0, 0, 0],
+        ),
+        (
+            Request {
+                id: 28,
+                command: Command::ReadRecording {
+                    name: "r".into(),
+                    offset: 3072,
+                },
+            },
+            vec![
+                28, 0, 0, 0, 36, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, b'r', 0x00, 0x0C, 0, 0,
+            ],
+        ),
+        (
+            Request {
+                id: 29,
+                command: Command::DeleteRecording("r".into()),
+            },
+            vec![29, 0, 0, 0, 37, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, b'r'],
+        ),
+        (
+            Request {
+                id: 30,
+                command: Command::ReplayHistory(3334),
+            },
+            vec![30, 0, 0, 0, 38, 0, 0, 0, 0x06, 0x0D],
+        ),
+        (
+            Request {
+                id: 31,
+                command: Command::GetCrashReport,
+            },
+            vec![31, 0, 0, 0, 39, 0, 0, 0],
+        ),
+        (
+            Request {
+                id: 32,
+                command: Command::ReadCoreDump(4096),
+            },
+            vec![32, 0, 0, 0, 40, 0, 0, 0, 0, 0x10, 0, 0],
+        ),
+        (
+            Request {
+                id: 33,
+                command: Command::EraseCoreDump,
+            },
+            vec![33, 0, 0, 0, 41, 0, 0, 0],
+        ),
+        (
+            Request {
+                id: 34,
+                command: Command::GetEventLog(5),
+            },
+            vec![34, 0, 0, 0, 42, 0, 0, 0, 5, 0, 0, 0],
+        ),
+        (
+            Request {
+                id: 35,
+                command: Command::ClearEventLog,
+            },
+            vec![35, 0, 0, 0, 43, 0, 0, 0],
+        ),
+    ];
+
+    for (request, bytes) in cases {
+        assert_eq!(encode(&request).unwrap(), bytes, "{request:?}");
+        assert_eq!(decode::<Request>(&bytes).unwrap(), request);
+    }
+}
+
+#[test]
+fn responses() {
+    let cases = [
+        (
+            Response {
+                id: 1,
+                result: Ok(Reply::Ok),
+            },
+            vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        ),
+        (
+            Response {
+                id: 1,
+                result: Ok(Reply::Hello {
+                    firmware_version: [0, 6, 4],
+                    protocol_version: 1,
+                }),
+            },
+            vec![1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 6, 4, 1],
+        ),
+        (
+            Response {
+                id: 3,
+                result: Err(CommandError::UnsupportedVersion(2)),
+            },
+            vec![3, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2],
+        ),
+        (
+            Response {
+                id: 0,
+                result: Err(CommandError::Malformed),
+            },
+            vec![0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0],
+        ),
+        (
+            Response {
+                id: 0,
+                result: Err(CommandError::FrameTooLarge(5000)),
+            },
+            vec![0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 0x88, 0x13, 0, 0],
+        ),
+        (
+            Response {
+                id: 9,
+                result: Err(CommandError::Storage("full".into())),
+            },
+            vec![
+                9, 0, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, b'f', b'u', b'l', b'l',
+            ],
+        ),
+        (
+            Response {
+                id: 11,
+                result: Ok(Reply::Challenge([0xAA; 32])),
+            },
+            [vec![11, 0, 0, 0, 0, 0, 0, 0, 9, 0, 0, 0], vec![0xAA; 32]].concat(),
+        ),
+        (
+            Response {
+                id: 2,
+                result: Err(CommandError::Unauthorized),
+            },
+            vec![2, 0, 0, 0, 1, 0, 0, 0, 9, 0, 0, 0],
+        ),
+        (
+            Response {
+                id: 14,
+                result: Ok(Reply::UploadProgress {
+                    written: 3074,
+                    total: 0x010000,
+                }),
+            },
+            vec![
+                14, 0, 0, 0, 0, 0, 0, 0, 10, 0, 0, 0, 0x02, 0x0C, 0, 0, 0, 0, 1, 0,
+            ],
+        ),
+        (
+            Response {
+                id: 18,
+                result: Ok(Reply::UpdateStatus(UpdateStatus::default())),
+            },
+            vec![18, 0, 0, 0, 0, 0, 0, 0, 12, 0, 0, 0, 0, 0, 0, 0, 0],
+        ),
+        (
+            Response {
+                id: 19,
+                result: Err(CommandError::UpdateInProgress),
+            },
+            vec![19, 0, 0, 0, 1, 0, 0, 0, 14, 0, 0, 0],
+        ),
+        (
+            Response {
+                id: 20,
+                result: Err(CommandError::InvalidSettings("x".into())),
+            },
+            vec![
+                20, 0, 0, 0, 1, 0, 0, 0, 16, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, b'x',
+            ],
+        ),
+        (
+            Response {
+                id: 21,
+                result: Err(CommandError::ReplayInProgress),
+            },
+            vec![21, 0, 0, 0, 1, 0, 0, 0, 20, 0, 0, 0],
+        ),
+        (
+            Response {
+                id: 22,
+                result: Err(CommandError::NoCoreDump),
+            },
+            vec![22, 0, 0, 0, 1, 0, 0, 0, 21, 0, 0, 0],
+        ),
+    ];
+
+    for (response, bytes) in cases {
+        assert_eq!(encode(&response).unwrap(), bytes, "{response:?}");
+        assert_eq!(decode::<Response>(&bytes).unwrap(), response);
+    }
+}
+
+#[test]
+fn read_back_replies() {
+    let cases = [
+        (
+            Reply::Config(DeviceConfig {
+                settings: Settings::default(),
+                udp_target: Some(SocketAddr::new(Ipv4Addr::new(192, 168, 1, 2).into(), 3333)),
+            }),
+            [
+                vec![2, 0, 0, 0],
+                default_settings_bytes(),
+                vec![1, 0, 0, 0, 0, 192, 168, 1, 2, 0x05, 0x0D],
+            ]
+            .concat(),
+        ),
+        (
+            Reply::DetectorParams(DetectorParams {
+                sample_rate: 400.0,
+                finger_threshold: 100_000.0,
+                finger_cooldown_ms: 500,
+                edge_threshold: -2000.0,
+                low_pass_cut_off: 5.0,
+                high_pass_cut_off: 0.5,
+                min_ibi_ms: 300,
+            }),
+            vec![
+                3, 0, 0, 0, 0, 0, 0xC8, 0x43, 0, 0x50, 0xC3, 0x47, 0xF4, 0x01, 0, 0, 0, 0, 0xFA,
+                0xC4, 0, 0, 0xA0, 0x40, 0, 0, 0, 0x3F, 0x2C, 0x01, 0, 0,
+            ],
+        ),
+        (
+            Reply::NetworkInfo(NetworkInfo {
+                connected: true,
+                mac: [0x98, 0x76, 0xB6, 0x12, 0xF9, 0x93],
+                ip: Some(Ipv4Addr::new(192, 168, 1, 20)),
+                netmask_bits: 24,
+                gateway: Some(Ipv4Addr::new(192, 168, 1, 1)),
+                dns: None,
+            }),
+            vec![
+                4, 0, 0, 0, 1, 0x98, 0x76, 0xB6, 0x12, 0xF9, 0x93, 1, 192, 168, 1, 20, 24, 1, 192,
+                168, 1, 1, 0,
+            ],
+        ),
+        (
+            Reply::Partitions(PartitionsInfo {
+                running: SlotInfo {
+                    label: "app0".into(),
+                    state: SlotState::Valid,
+                    version: Some("0.6.4".into()),
+                },
+                update: SlotInfo {
+                    label: "app1".into(),
+                    state: SlotState::Unknown,
+                    version: None,
+                },
+            }),
+            vec![
+                5, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, b'a', b'p', b'p', b'0', 0, 0, 0, 0, 1, 5, 0, 0,
+                0, 0, 0, 0, 0, b'0', b'.', b'6', b'.', b'4', 4, 0, 0, 0, 0, 0, 0, 0, b'a', b'p',
+                b'p', b'1', 3, 0, 0, 0, 0,
+            ],
+        ),
+        (
+            Reply::SensorHealth(SensorHealth {
+                heart_ok: true,
+                haptic_ok: false,
+                display_ok: true,
+                finger_detected: true,
+                samples_read: 2,
+                fifo_errors: 1,
+                last_sample_us: Some(3),
+            }),
+            vec![
+                6, 0, 0, 0, 1, 0, 1, 1, 2, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1, 3, 0, 0, 0, 0, 0, 0,
+                0,
+            ],
+        ),
+        (
+            Reply::Subscribers(vec![SubscriberInfo {
+                addr: SocketAddr::new(Ipv4Addr::new(10, 0, 0, 5).into(), 3333),
+                streams: Streams::ALL,
+                sample_rate_hz: 25,
+                legacy: true,
+            }]),
+            vec![
+                7, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 10, 0, 0, 5, 0x05, 0x0D, 1, 1, 1,
+                1, 1, 25, 0, 1,
+            ],
+        ),
+        (
+            Reply::Clients(vec![ClientInfo {
+                id: 1,
+                addr: SocketAddr::new(Ipv4Addr::new(10, 0, 0, 5).into(), 50000),
+                connected_at_us: 256,
+                last_active_us: 512,
+                requests: 3,
+                legacy: false,
+            }]),
+            vec![
+                8, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 10, 0, 0, 5, 0x50,
+                0xC3, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0,
+            ],
+        ),
+        (
+            Reply::UpdateOutcome(Some(UpdateOutcome::Verified {
+                version: "0.7.0".into(),
+            })),
+            vec![
+                11, 0, 0, 0, 1, 0, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, b'0', b'.', b'7', b'.', b'0',
+            ],
+        ),
+        (
+            Reply::UpdateOutcome(Some(UpdateOutcome::RolledBack {
+                version: None,
+                reason: "crash".into(),
+            })),
+            vec![
+                11, 0, 0, 0, 1, 1, 0, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, b'c', b'r', b'a', b's', b'h',
+            ],
+        ),
+        (Reply::UpdateOutcome(None), vec![11, 0, 0, 0, 0]),
+        (
+            Reply::UpdateCheck(UpdateCheck {
+                running: [0, 6, 4],
+                latest: [0, 7, 0],
+                image: "u".into(),
+                available: true,
+            }),
+            vec![
+                13, 0, 0, 0, 0, 6, 4, 0, 7, 0, 1, 0, 0, 0, 0, 0, 0, 0, b'u', 1,
+            ],
+        ),
+        (
+            Reply::BootInfo(BootInfo {
+                running: "a".into(),
+                next_boot: "b".into(),
+                slots: vec![SlotInfo {
+                    label: "a".into(),
+                    state: SlotState::Invalid,
+                    version: None,
+                }],
+                reset_reason: ResetReason::TaskWatchdog,
+                boot_count: 258,
+            }),
+            vec![
+                14, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, b'a', 1, 0, 0, 0, 0, 0, 0, 0, b'b', 1, 0, 0,
+                0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, b'a', 1, 0, 0, 0, 0, 6, 0, 0, 0, 2, 1, 0, 0,
+            ],
+        ),
+        (
+            Reply::Settings(Settings::default()),
+            [vec![15, 0, 0, 0], default_settings_bytes()].concat(),
+        ),
+        (
+            Reply::Recordings(RecordingList {
+                recordings: vec![RecordingInfo {
+                    name: "r".into(),
+                    size: 300,
+                }],
+                active: None,
+                used_bytes: 512,
+                total_bytes: 65536,
+            }),
+            vec![
+                17, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, b'r', 0x2C, 1, 0, 0,
+                0, 0, 2, 0, 0, 0, 0, 1, 0,
+            ],
+        ),
+        (
+            Reply::RecordingChunk {
+                offset: 1,
+                total: 3,
+                data: vec![7, 8],
+            },
+            vec![
+                18, 0, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 7, 8,
+            ],
+        ),
+        (
+            Reply::CrashReport(CrashReport {
+                core_dump_size: Some(0x1000),
+                last_panic: Some("x".into()),
+            }),
+            vec![
+                19, 0, 0, 0, 1, 0, 0x10, 0, 0, 1, 1, 0, 0, 0, 0, 0, 0, 0, b'x',
+            ],
+        ),
+        (
+            Reply::CrashReport(CrashReport::default()),
+            vec![19, 0, 0, 0, 0, 0],
+        ),
+        (
+            Reply::CoreDumpChunk {
+                offset: 0,
+                total: 1,
+                data: vec![9],
+            },
+            vec![
+                20, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 9,
+            ],
+        ),
+        (
+            Reply::EventLog(vec![LogEntry {
+                seq: 3,
+                boot_count: 7,
+                timestamp_us: 1000,
+                severity: Severity::Error,
+                subsystem: "ota".into(),
+                message: "x".into(),
+                repeated: 2,
+            }]),
+            vec![
+                21, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 7, 0, 0, 0, 0xE8, 0x03, 0, 0, 0,
+                0, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, b'o', b't', b'a', 1, 0, 0, 0, 0, 0, 0,
+                0, b'x', 2, 0, 0, 0,
+            ],
+        ),
+    ];
+
+    for (reply, bytes) in cases {
+        assert_eq!(encode(&reply).unwrap(), bytes, "{reply:?}");
+        assert_eq!(decode::<Reply>(&bytes).unwrap(), reply);
+    }
+}
+
+/// Encoding of [`Settings::default`], without the version header of the NVS blob
+fn default_settings_bytes() -> Vec<u8> {
+    vec![
+        35, 0x00, 0x50, 0xC3, 0x47, 0xF4, 0x01, 0, 0, 0x00, 0x00, 0xFA, 0xC4, 0x2C, 0x01, 0, 0, 1,
+        255, 0, 0, 0, 0, 0, 10, 0, 5, 16, 0, 1, 10,
+    ]
+}
+
+#[test]
+fn frames() {
+    let mut frame = Vec::new();
+    write_frame(&mut frame, &[1, 2, 3]).unwrap();
+    assert_eq!(frame, [FRAME_MAGIC, PROTOCOL_VERSION, 3, 0, 0, 0, 1, 2, 3]);
+
+    for partial in 0..frame.len() {
+        assert!(matches!(parse_frame(&frame[..partial]), Frame::Incomplete));
+    }
+
+    frame.extend_from_slice(&[FRAME_MAGIC]);
+    match parse_frame(&frame) {
+        Frame::Complete {
+            version,
+            payload,
+            len,
+        } => {
+            assert_eq!(version, PROTOCOL_VERSION);
+            assert_eq!(payload, [1, 2, 3]);
+            assert_eq!(len, 9);
+        }
+        other => panic!("unexpected {other:?}"),
+    }
+
+    assert!(matches!(
+        parse_frame(&[FRAME_MAGIC, 1, 0xFF, 0xFF, 0, 0]),
+        Frame::TooLarge(0xFFFF)
+    ));
+    assert!(matches!(parse_frame(&[2, 35]), Frame::Legacy));
+}
+
+#[test]
+fn legacy_commands() {
+    assert_eq!(parse_legacy(&[0]), Some(Command::Restart));
+    assert_eq!(
+        parse_legacy(b"\x010.6.4"),
+        Some(Command::Update("0.6.4".into()))
+    );
+    assert_eq!(parse_legacy(&[2, 35]), Some(Command::SetLedAmplitude(35)));
+    assert_eq!(
+        parse_legacy(&[3, 0x12, 0x34]),
+        Some(Command::ConnectUdp(0x1234))
+    );
+    assert_eq!(
+        parse_legacy(&[4, 200]),
+        Some(Command::SetHapticAmplitude(200))
+    );
+    assert_eq!(parse_legacy(&[2]), None);
+    assert_eq!(parse_legacy(&[3, 0x12]), None);
+    assert_eq!(parse_legacy(&[5]), None);
+}
+
+#[test]
+fn recording_chunk_fits_frame() {
+    let response = Response {
+        id: u32::MAX,
+        result: Ok(Reply::RecordingChunk {
+            offset: u32::MAX,
+            total: u32::MAX,
+            data: vec![0; RECORDING_CHUNK_LEN],
+        }),
+    };
+    assert!(encode(&response).unwrap().len() <= MAX_FRAME_LEN);
+}
+
+#[test]
+fn core_dump_chunk_fits_frame() {
+    let response = Response {
+        id: u32::MAX,
+        result: Ok(Reply::CoreDumpChunk {
+            offset: u32::MAX,
+            total: u32::MAX,
+            data: vec![0; CORE_DUMP_CHUNK_LEN],
+        }),
+    };
+    assert!(encode(&response).unwrap().len() <= MAX_FRAME_LEN);
+}
+
+#[test]
+fn event_log_page_fits_frame() {
+    let entry = LogEntry {
+        seq: u32::MAX,
+        boot_count: u32::MAX,
+        timestamp_us: u64::MAX,
+        severity: Severity::Error,
+        subsystem: "s".repeat(EVENT_LOG_SUBSYSTEM_MAX_LEN),
+        message: "m".repeat(EVENT_LOG_MESSAGE_MAX_LEN),
+        repeated: u32::MAX,
+    };
+    let response = Response {
+        id: u32::MAX,
+        result: Ok(Reply::EventLog(vec![entry; EVENT_LOG_PAGE_LEN])),
+    };
+    assert!(encode(&response).unwrap().len() <= MAX_FRAME_LEN);
+}
+
+#[test]
+fn upload_chunk_fits_frame() {
+    let request = Request {
+        id: u32::MAX,
+        command: Command::UploadChunk {
+            offset: u32::MAX,
+            data: vec![0; UPLOAD_CHUNK_LEN],
+        },
+    };
+    assert!(encode(&request).unwrap().len() <= MAX_FRAME_LEN);
+}
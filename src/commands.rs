@@ -0,0 +1,628 @@
+use std::io::{self, Read};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use esp_idf_svc::hal::reset::restart;
+use esp_idf_svc::ipv4::IpInfo;
+use esp_idf_svc::nvs::*;
+use http::Uri;
+
+use crate::pulse_sensor::*;
+use crate::recording::Recorder;
+use crate::settings::{self, SettingsStore};
+use crate::streaming::{self, Subscribers};
+use crate::{crash, event_log, factory_reset, firmware_version, ota, self_test};
+use esp_pulser::{micros_since_boot, random_bytes, ETH_MAC};
+use esp_pulser_protocol::*;
+
+pub const COMMAND_PORT: u16 = 12345;
+
+const PAIRING_SECRET_KEY: &str = "pair_secret";
+
+/// Device state shared with the command server.
+pub struct CommandContext {
+    pub subscribers: Arc<Mutex<Subscribers>>,
+    pub status: Arc<Mutex<Status>>,
+    pub health: Arc<Mutex<SensorHealth>>,
+    pub ip_info: Arc<Mutex<Option<IpInfo>>>,
+    pub nvs: Arc<Mutex<EspNvs<NvsDefault>>>,
+    pub settings: Arc<SettingsStore>,
+    pub recorder: Arc<Recorder>,
+    pub update: Arc<ota::UpdateJob>,
+}
+
+struct Server {
+    ctx: CommandContext,
+    clients: Mutex<Vec<ClientInfo>>,
+    next_client_id: AtomicU32,
+}
+
+impl Server {
+    fn register_client(&self, addr: SocketAddr) -> Option<u32> {
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() >= MAX_CLIENTS {
+            return None;
+        }
+        let id = self.next_client_id.fetch_add(1, Ordering::Relaxed);
+        let now = micros_since_boot();
+        clients.push(ClientInfo {
+            id,
+            addr,
+            connected_at_us: now,
+            last_active_us: now,
+            requests: 0,
+            legacy: false,
+        });
+        Some(id)
+    }
+
+    fn unregister_client(&self, id: u32) {
+        self.clients.lock().unwrap().retain(|c| c.id != id);
+    }
+
+    fn touch_client(&self, conn: &Connection) {
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(client) = clients.iter_mut().find(|c| c.id == conn.id) {
+            client.last_active_us = micros_since_boot();
+            client.requests += 1;
+            client.legacy = conn.legacy;
+        }
+    }
+}
+
+/// Per-connection state of a command client.
+struct Connection {
+    id: u32,
+    addr: SocketAddr,
+    stream: TcpStream,
+    pending: Vec<u8>,
+    legacy: bool,
+    /// Challenge handed out to the client and not yet answered
+    challenge: Option<Challenge>,
+    authenticated: bool,
+}
+
+pub fn tcp_receiver_task(ctx: CommandContext) {
+    let tcp_socket =
+        TcpListener::bind(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), COMMAND_PORT)).unwrap();
+
+    let server = Arc::new(Server {
+        ctx,
+        clients: Mutex::new(Vec::new()),
+        next_client_id: AtomicU32::new(1),
+    });
+
+    log::info!("Listening for GUI client...");
+
+    loop {
+        match tcp_socket.accept() {
+            Ok((mut stream, addr)) => {
+                log::info!("Connection from: {:?}", addr);
+
+                let Some(id) = server.register_client(addr) else {
+                    log::warn!("Rejecting {}, too many clients", addr);
+                    send_response(
+                        &mut stream,
+                        &Response {
+                            id: 0,
+                            result: Err(CommandError::TooManyClients),
+                        },
+                    );
+                    continue;
+                };
+
+                let conn = Connection {
+                    id,
+                    addr,
+                    stream,
+                    pending: Vec::new(),
+                    legacy: false,
+                    challenge: None,
+                    authenticated: false,
+                };
+                let server_clone = server.clone();
+                let spawned = thread::Builder::new().stack_size(8 * 1024).spawn(move || {
+                    handle_connection(&server_clone, conn);
+                    server_clone.unregister_client(id);
+                });
+                if let Err(e) = spawned {
+                    log::warn!("Error spawning client thread: {:?}", e);
+                    server.unregister_client(id);
+                }
+            }
+            Err(e) => {
+                log::warn!("Error accepting connection: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Something received from a command client.
+enum Incoming {
+    Legacy(Command),
+    Request(Request),
+    Invalid(CommandError),
+}
+
+impl Connection {
+    /// Wait for the next command, returning `None` once the connection is gone.
+    fn next_incoming(&mut self) -> Option<Incoming> {
+        loop {
+            match parse_frame(&self.pending) {
+                Frame::Incomplete => (),
+                Frame::Legacy => {
+                    // Legacy clients send exactly one command per write, with no length
+                    // information, so the whole read is taken as a single command. They
+                    // have no way to keep the connection alive, so they get a longer
+                    // timeout, which still frees the slot of a client that vanished.
+                    if !self.legacy {
+                        self.legacy = true;
+                        let timeout = Duration::from_secs(LEGACY_IDLE_TIMEOUT_SECS);
+                        let _ = self.stream.set_read_timeout(Some(timeout));
+                    }
+                    log::info!("Received TCP command: {:?}", self.pending[0]);
+                    let command = parse_legacy(&self.pending);
+                    self.pending.clear();
+                    match command {
+                        Some(command) => return Some(Incoming::Legacy(command)),
+                        None => log::info!("Unknown command"),
+                    }
+                }
+                Frame::TooLarge(len) => {
+                    log::warn!("Rejecting frame of {} bytes", len);
+                    self.respond(0, Err(CommandError::FrameTooLarge(len)));
+                    return None;
+                }
+                Frame::Complete {
+                    version,
+                    payload,
+                    len,
+                } => {
+                    let request = if version != PROTOCOL_VERSION {
+                        Err(CommandError::UnsupportedVersion(version))
+                    } else {
+                        decode::<Request>(payload).map_err(|_| CommandError::Malformed)
+                    };
+                    self.pending.drain(..len);
+                    return Some(match request {
+                        Ok(request) => Incoming::Request(request),
+                        Err(e) => Incoming::Invalid(e),
+                    });
+                }
+            }
+
+            let mut buf = [0; 1024];
+            match self.stream.read(&mut buf) {
+                Ok(0) => {
+                    log::info!("Connection {} closed", self.addr);
+                    return None;
+                }
+                Ok(n) => self.pending.extend_from_slice(&buf[..n]),
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    log::info!("Connection {} idle, closing", self.addr);
+                    return None;
+                }
+                Err(e) => {
+                    log::warn!("Error receiving data: {:?}", e);
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn respond(&mut self, id: u32, result: Result<Reply, CommandError>) {
+        send_response(&mut self.stream, &Response { id, result });
+    }
+}
+
+fn handle_connection(server: &Server, mut conn: Connection) {
+    if let Err(e) = conn
+        .stream
+        .set_read_timeout(Some(Duration::from_secs(CLIENT_IDLE_TIMEOUT_SECS)))
+    {
+        log::warn!("Error setting client timeout: {:?}", e);
+    }
+
+    while let Some(incoming) = conn.next_incoming() {
+        server.touch_client(&conn);
+        match incoming {
+            Incoming::Legacy(command) => {
+                let result = handle_command(server, &mut conn, &command);
+                if let Err(e) = &result {
+                    log::warn!("Legacy command failed: {}", e);
+                }
+                restart_if_requested(&command, &result);
+            }
+            Incoming::Request(request) => {
+                log::info!("Received request: {:?}", request);
+                let result = handle_command(server, &mut conn, &request.command);
+                let restart = request.command.restarts_after(&result);
+                let succeeded = result.is_ok();
+                conn.respond(request.id, result);
+                if succeeded {
+                    if let Command::UploadBegin {
+                        size,
+                        sha256,
+                        signature,
+                        force,
+                    } = request.command
+                    {
+                        receive_upload(server, &mut conn, size, &sha256, &signature, force);
+                    }
+                }
+                if restart {
+                    restart();
+                }
+            }
+            Incoming::Invalid(e) => {
+                log::warn!("Rejecting request: {}", e);
+                conn.respond(0, Err(e));
+            }
+        }
+    }
+}
+
+/// Feeds an image pushed by the client into the OTA partition. The chunks and the
+/// final `UploadFinish` are all answered here, as part of the upload session.
+fn receive_upload(
+    server: &Server,
+    conn: &mut Connection,
+    size: u32,
+    sha256: &ImageDigest,
+    signature: &[u8],
+    force: bool,
+) {
+    log::info!("Receiving firmware upload of {} bytes", size);
+    let job = &server.ctx.update;
+    let mut source = UploadSource {
+        conn,
+        request_id: 0,
+        total: size,
+    };
+    let result = ota::upload_firmware(job, size as usize, sha256, signature, force, &mut source);
+    let id = source.request_id;
+    job.finish(&result);
+
+    match result {
+        Ok(()) => {
+            log::info!("Firmware upload complete, restarting...");
+            conn.respond(id, Ok(Reply::Ok));
+            restart();
+        }
+        Err(e) => {
+            log::warn!("Firmware upload failed: {}", e);
+            conn.respond(id, Err(CommandError::Update(e.to_string())));
+        }
+    }
+}
+
+struct UploadSource<'c> {
+    conn: &'c mut Connection,
+    /// The request to answer once the pending chunk has been written
+    request_id: u32,
+    total: u32,
+}
+
+impl ota::FirmwareSource for UploadSource<'_> {
+    fn next_chunk(&mut self, written: usize) -> anyhow::Result<Option<Vec<u8>>> {
+        loop {
+            let Some(incoming) = self.conn.next_incoming() else {
+                anyhow::bail!("connection lost");
+            };
+            match incoming {
+                Incoming::Request(Request { id, command }) => {
+                    self.request_id = id;
+                    match command {
+                        Command::UploadChunk { offset, data } if offset as usize == written => {
+                            return Ok(Some(data));
+                        }
+                        Command::UploadChunk { offset, .. } => {
+                            anyhow::bail!("chunk at offset {offset}, expected {written}");
+                        }
+                        Command::UploadFinish => return Ok(None),
+                        Command::UploadAbort => anyhow::bail!("aborted by client"),
+                        _ => self.conn.respond(id, Err(CommandError::UploadInProgress)),
+                    }
+                }
+                Incoming::Invalid(e) => self.conn.respond(0, Err(e)),
+                Incoming::Legacy(command) => {
+                    log::warn!("Ignoring {:?} during firmware upload", command);
+                }
+            }
+        }
+    }
+
+    fn progress(&mut self, written: usize) {
+        let reply = Reply::UploadProgress {
+            written: written as u32,
+            total: self.total,
+        };
+        self.conn.respond(self.request_id, Ok(reply));
+    }
+}
+
+fn handle_command(
+    server: &Server,
+    conn: &mut Connection,
+    command: &Command,
+) -> Result<Reply, CommandError> {
+    let ctx = &server.ctx;
+    let addr = conn.addr;
+
+    command
+        .authorize(conn.authenticated, || Ok(pairing_secret(ctx)?.is_some()))
+        .inspect_err(|e| {
+            if *e == CommandError::Unauthorized {
+                log::warn!("Unauthenticated {:?} from {}", command, addr);
+            }
+        })?;
+    match command {
+        Command::Hello => Ok(Reply::Hello {
+            firmware_version: firmware_version(),
+            protocol_version: PROTOCOL_VERSION,
+        }),
+        Command::Restart => {
+            log::info!("Restarting...");
+            Ok(Reply::Ok)
+        }
+        Command::Update(data) | Command::ForceUpdate(data) => {
+            log::info!("Attempting update...");
+            let Some(url) = ota::update_url(data) else {
+                log::warn!("Invalid URL to download firmware");
+                return Err(CommandError::Update("invalid URL or tag".into()));
+            };
+            log::info!("Update URL: {}", url);
+            let force = matches!(command, Command::ForceUpdate(_));
+            let network = ctx.settings.read(|s| s.network.clone());
+            ota::start_download(ctx.update.clone(), url, force, &network)?;
+            Ok(Reply::Ok)
+        }
+        Command::CheckForUpdate => {
+            let base_url = release_url(ctx);
+            log::info!("Checking for updates at {}", base_url);
+            let network = ctx.settings.read(|s| s.network.clone());
+            ota::check_for_update(&base_url, &network)
+                .map(Reply::UpdateCheck)
+                .map_err(|e| CommandError::Network(e.to_string()))
+        }
+        Command::SetReleaseUrl(url) => {
+            let url = (!url.is_empty()).then(|| url.clone());
+            if url
+                .as_deref()
+                .is_some_and(|url| Uri::try_from(url).is_err())
+            {
+                return Err(CommandError::InvalidSettings("invalid release URL".into()));
+            }
+            ctx.settings.update(|s| s.network.release_url = url)?;
+            Ok(Reply::Ok)
+        }
+        Command::CancelUpdate => {
+            ctx.update.cancel()?;
+            Ok(Reply::Ok)
+        }
+        Command::GetUpdateStatus => Ok(Reply::UpdateStatus(ctx.update.status())),
+        Command::SetLedAmplitude(led_amplitude) => {
+            log::info!("Setting LED amplitude to: {}", led_amplitude);
+            let settings = ctx
+                .settings
+                .update(|s| s.sensor.led_amplitude = *led_amplitude)?;
+            settings::apply_to_status(&settings, &ctx.status);
+            Ok(Reply::Ok)
+        }
+        Command::ConnectUdp(port) => {
+            let udp_target = SocketAddr::new(addr.ip(), *port);
+            log::info!("Connecting to UDP socket at: {}", udp_target);
+            ctx.subscribers
+                .lock()
+                .unwrap()
+                .subscribe_legacy(udp_target)?;
+            Ok(Reply::Ok)
+        }
+        Command::SetHapticAmplitude(haptic_amplitude) => {
+            log::info!("Setting Motor amplitude to: {}", haptic_amplitude);
+            let settings = ctx
+                .settings
+                .update(|s| s.haptic.amplitude = *haptic_amplitude)?;
+            settings::apply_to_status(&settings, &ctx.status);
+            Ok(Reply::Ok)
+        }
+        Command::GetConfig => Ok(Reply::Config(DeviceConfig {
+            settings: ctx.settings.get(),
+            udp_target: ctx.subscribers.lock().unwrap().legacy_target(),
+        })),
+        Command::GetDetectorParams => {
+            let detector = ctx.settings.read(|s| s.detector);
+            Ok(Reply::DetectorParams(DetectorParams {
+                sample_rate: SAMPLE_RATE,
+                finger_threshold: detector.finger_threshold,
+                finger_cooldown_ms: detector.finger_cooldown_ms,
+                edge_threshold: detector.edge_threshold,
+                low_pass_cut_off: LP_CUT_OFF,
+                high_pass_cut_off: HP_CUT_OFF,
+                min_ibi_ms: detector.min_ibi_ms,
+            }))
+        }
+        Command::FactoryReset => {
+            factory_reset::request(&ctx.nvs).map_err(|e| CommandError::Storage(e.to_string()))?;
+            log::warn!("Factory reset requested by {}, restarting...", addr);
+            Ok(Reply::Ok)
+        }
+        Command::StartRecording => ctx.recorder.start().map(Reply::Recording),
+        Command::StopRecording => ctx.recorder.stop().map(Reply::Recording),
+        Command::ListRecordings => ctx.recorder.list().map(Reply::Recordings),
+        Command::ReadRecording { name, offset } => ctx.recorder.read(name, *offset),
+        Command::DeleteRecording(name) => {
+            ctx.recorder.delete(name)?;
+            Ok(Reply::Ok)
+        }
+        Command::ExportSettings => Ok(Reply::Settings(ctx.settings.get())),
+        Command::ImportSettings(settings) => {
+            log::info!("Importing settings: {:?}", settings);
+            let settings = ctx.settings.update(|s| *s = settings.clone())?;
+            settings::apply_to_status(&settings, &ctx.status);
+            Ok(Reply::Ok)
+        }
+        Command::GetNetworkInfo => {
+            let ip_info = *ctx.ip_info.lock().unwrap();
+            Ok(Reply::NetworkInfo(NetworkInfo {
+                connected: ctx.status.lock().unwrap().connected,
+                mac: ETH_MAC,
+                ip: ip_info.map(|info| info.ip),
+                netmask_bits: ip_info.map(|info| info.subnet.mask.0).unwrap_or_default(),
+                gateway: ip_info.map(|info| info.subnet.gateway),
+                dns: ip_info.and_then(|info| info.dns),
+            }))
+        }
+        Command::GetPartitions => ota::partitions_info()
+            .map(Reply::Partitions)
+            .map_err(|e| CommandError::Storage(e.to_string())),
+        Command::GetBootInfo => {
+            let (reset_reason, boot_count) = {
+                let status = ctx.status.lock().unwrap();
+                (status.reset_reason, status.boot_count)
+            };
+            ota::boot_info(reset_reason, boot_count)
+                .map(Reply::BootInfo)
+                .map_err(|e| CommandError::Storage(e.to_string()))
+        }
+        Command::SwitchBootSlot => {
+            if ctx.update.status().is_active() {
+                return Err(CommandError::UpdateInProgress);
+            }
+            ota::switch_boot_slot().map_err(|e| CommandError::Update(e.to_string()))?;
+            log::info!("Restarting into the other slot...");
+            Ok(Reply::Ok)
+        }
+        Command::GetUpdateOutcome => self_test::update_outcome(&ctx.nvs)
+            .map(Reply::UpdateOutcome)
+            .map_err(|e| CommandError::Storage(e.to_string())),
+        Command::GetSensorHealth => {
+            let mut health = ctx.health.lock().unwrap().clone();
+            let status = ctx.status.lock().unwrap();
+            health.heart_ok = status.heart_ok;
+            health.haptic_ok = status.haptic_ok;
+            health.display_ok = status.display_ok;
+            Ok(Reply::SensorHealth(health))
+        }
+        Command::Subscribe(subscription) => {
+            let target = SocketAddr::new(addr.ip(), subscription.port);
+            ctx.subscribers
+                .lock()
+                .unwrap()
+                .subscribe(target, subscription)?;
+            Ok(Reply::Ok)
+        }
+        Command::Unsubscribe(port) => {
+            let target = SocketAddr::new(addr.ip(), *port);
+            ctx.subscribers.lock().unwrap().unsubscribe(target)?;
+            Ok(Reply::Ok)
+        }
+        Command::ReplayHistory(port) => {
+            let target = SocketAddr::new(addr.ip(), *port);
+            streaming::replay_history(&ctx.subscribers, target)?;
+            Ok(Reply::Ok)
+        }
+        Command::GetCrashReport => crash::report(&ctx.nvs).map(Reply::CrashReport),
+        Command::ReadCoreDump(offset) => crash::read_core_dump(*offset),
+        Command::EraseCoreDump => {
+            crash::erase(&ctx.nvs)?;
+            ctx.status.lock().unwrap().core_dump = false;
+            Ok(Reply::Ok)
+        }
+        Command::GetEventLog(from) => Ok(Reply::EventLog(event_log::page(*from))),
+        Command::ClearEventLog => {
+            event_log::clear().map_err(|e| CommandError::Storage(e.to_string()))?;
+            Ok(Reply::Ok)
+        }
+        Command::ListSubscribers => Ok(Reply::Subscribers(ctx.subscribers.lock().unwrap().list())),
+        Command::ListClients => Ok(Reply::Clients(server.clients.lock().unwrap().clone())),
+        Command::AuthChallenge => {
+            if pairing_secret(ctx)?.is_none() {
+                return Err(CommandError::NotPaired);
+            }
+            let challenge = random_bytes();
+            conn.challenge = Some(challenge);
+            Ok(Reply::Challenge(challenge))
+        }
+        Command::Authenticate(response) => {
+            let secret = pairing_secret(ctx)?.ok_or(CommandError::NotPaired)?;
+            // A challenge can only be answered once
+            let challenge = conn.challenge.take().ok_or(CommandError::AuthFailed)?;
+            if !verify_auth_response(&secret, &challenge, response) {
+                log::warn!("Failed authentication from {}", addr);
+                return Err(CommandError::AuthFailed);
+            }
+            log::info!("Client {} authenticated", addr);
+            conn.authenticated = true;
+            Ok(Reply::Ok)
+        }
+        Command::UploadBegin {
+            size,
+            sha256,
+            signature,
+            ..
+        } => {
+            ota::check_firmware_size(*size as usize)
+                .and_then(|_| ota::verify_signature(sha256, signature))
+                .map_err(|e| CommandError::Update(e.to_string()))?;
+            ctx.update.begin()?;
+            Ok(Reply::UploadProgress {
+                written: 0,
+                total: *size,
+            })
+        }
+        Command::UploadChunk { .. } | Command::UploadFinish | Command::UploadAbort => {
+            Err(CommandError::NoUpload)
+        }
+        Command::Pair(secret) => {
+            log::info!("Storing new pairing secret");
+            ctx.nvs
+                .lock()
+                .unwrap()
+                .set_blob(PAIRING_SECRET_KEY, secret)
+                .map_err(|e| CommandError::Storage(e.to_string()))?;
+            conn.authenticated = true;
+            Ok(Reply::Ok)
+        }
+    }
+}
+
+/// Base URL of the release manifest, as configured with `SetReleaseUrl`.
+fn release_url(ctx: &CommandContext) -> String {
+    ctx.settings
+        .read(|s| s.network.release_url.clone())
+        .unwrap_or_else(|| ota::DEFAULT_RELEASE_URL.to_string())
+}
+
+fn pairing_secret(ctx: &CommandContext) -> Result<Option<PairingSecret>, CommandError> {
+    let mut secret = [0; PAIRING_SECRET_LEN];
+    let nvs = ctx.nvs.lock().unwrap();
+    match nvs.get_blob(PAIRING_SECRET_KEY, &mut secret) {
+        Ok(Some(stored)) if stored.len() == PAIRING_SECRET_LEN => Ok(Some(secret)),
+        Ok(_) => Ok(None),
+        Err(e) => Err(CommandError::Storage(e.to_string())),
+    }
+}
+
+/// Restart only takes effect once the reply has been sent, and only if the command
+/// succeeded.
+fn restart_if_requested(command: &Command, result: &Result<Reply, CommandError>) {
+    if command.restarts_after(result) {
+        restart();
+    }
+}
+
+fn send_response(stream: &mut TcpStream, response: &Response) {
+    let payload = encode(response).unwrap();
+    if let Err(e) = write_frame(stream, &payload) {
+        log::warn!("Error sending response: {:?}", e);
+    }
+}
@@ -0,0 +1,147 @@
+//! Crash reports.
+//!
+//! ESP-IDF writes a core dump to the `coredump` partition when the firmware crashes,
+//! which survives the reset and can be read out over the command connection. The
+//! message of a Rust panic does not make it into the core dump, so a panic hook stores
+//! it in NVS before the panic aborts.
+
+use std::ffi::{c_void, CString};
+use std::panic;
+use std::ptr;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use esp_idf_svc::nvs::*;
+use esp_idf_svc::sys::{
+    esp, esp_core_dump_image_check, esp_core_dump_image_erase, esp_core_dump_image_get,
+    esp_partition_find_first, esp_partition_read,
+    esp_partition_subtype_t_ESP_PARTITION_SUBTYPE_DATA_COREDUMP,
+    esp_partition_type_t_ESP_PARTITION_TYPE_DATA, nvs_close, nvs_commit, nvs_handle_t, nvs_open,
+    nvs_open_mode_t_NVS_READWRITE, nvs_set_str, ESP_OK,
+};
+
+use crate::factory_reset::NVS_NAMESPACE;
+use esp_pulser_protocol::*;
+
+const LAST_PANIC_KEY: &str = "last_panic";
+/// Longer panic messages are cut off
+const PANIC_MESSAGE_MAX_LEN: usize = 256;
+
+/// Store the message of every panic in NVS, then carry on with the default hook. Needs
+/// the NVS partition to be initialized.
+pub fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let mut message = info.to_string();
+        if message.len() > PANIC_MESSAGE_MAX_LEN {
+            let mut end = PANIC_MESSAGE_MAX_LEN;
+            while !message.is_char_boundary(end) {
+                end -= 1;
+            }
+            message.truncate(end);
+        }
+        // Bypasses the `EspNvs` mutex, which the panicking thread may be holding
+        if let Err(e) = store_panic(&message) {
+            log::error!("Failed to store panic message: {:?}", e);
+        }
+        default_hook(info);
+    }));
+}
+
+fn store_panic(message: &str) -> Result<()> {
+    let key = CString::new(LAST_PANIC_KEY)?;
+    let message = CString::new(message.replace('\0', " "))?;
+    let mut handle: nvs_handle_t = 0;
+    unsafe {
+        esp!(nvs_open(
+            NVS_NAMESPACE.as_ptr(),
+            nvs_open_mode_t_NVS_READWRITE,
+            &mut handle
+        ))?;
+        let result = esp!(nvs_set_str(handle, key.as_ptr(), message.as_ptr()))
+            .and_then(|_| esp!(nvs_commit(handle)));
+        nvs_close(handle);
+        result?;
+    }
+    Ok(())
+}
+
+/// Address in flash and size of the stored core dump, if there is a valid one.
+fn core_dump_image() -> Option<(usize, usize)> {
+    if unsafe { esp_core_dump_image_check() } != ESP_OK {
+        return None;
+    }
+    let (mut addr, mut size) = (0, 0);
+    esp!(unsafe { esp_core_dump_image_get(&mut addr, &mut size) }).ok()?;
+    Some((addr, size))
+}
+
+pub fn core_dump_stored() -> bool {
+    core_dump_image().is_some()
+}
+
+pub fn report(nvs: &Mutex<EspNvs<NvsDefault>>) -> Result<CrashReport, CommandError> {
+    let mut buf = [0; PANIC_MESSAGE_MAX_LEN + 1];
+    let last_panic = nvs
+        .lock()
+        .unwrap()
+        .get_str(LAST_PANIC_KEY, &mut buf)
+        .map_err(storage_error)?
+        .map(str::to_string);
+    Ok(CrashReport {
+        core_dump_size: core_dump_image().map(|(_, size)| size as u32),
+        last_panic,
+    })
+}
+
+/// Read a chunk of the core dump, as stored in flash.
+pub fn read_core_dump(offset: u32) -> Result<Reply, CommandError> {
+    let (addr, size) = core_dump_image().ok_or(CommandError::NoCoreDump)?;
+    let partition = unsafe {
+        esp_partition_find_first(
+            esp_partition_type_t_ESP_PARTITION_TYPE_DATA,
+            esp_partition_subtype_t_ESP_PARTITION_SUBTYPE_DATA_COREDUMP,
+            ptr::null(),
+        )
+    };
+    if partition.is_null() {
+        return Err(CommandError::NoCoreDump);
+    }
+
+    let mut data = vec![0u8; CORE_DUMP_CHUNK_LEN.min(size.saturating_sub(offset as usize))];
+    if !data.is_empty() {
+        let start = addr - unsafe { (*partition).address } as usize + offset as usize;
+        esp!(unsafe {
+            esp_partition_read(
+                partition,
+                start,
+                data.as_mut_ptr() as *mut c_void,
+                data.len(),
+            )
+        })
+        .map_err(storage_error)?;
+    }
+    Ok(Reply::CoreDumpChunk {
+        offset,
+        total: size as u32,
+        data,
+    })
+}
+
+/// Erase the core dump along with the panic message, so the next report only shows
+/// what happened after.
+pub fn erase(nvs: &Mutex<EspNvs<NvsDefault>>) -> Result<(), CommandError> {
+    if core_dump_stored() {
+        esp!(unsafe { esp_core_dump_image_erase() }).map_err(storage_error)?;
+    }
+    nvs.lock()
+        .unwrap()
+        .remove(LAST_PANIC_KEY)
+        .map_err(storage_error)?;
+    log::info!("Crash report erased");
+    Ok(())
+}
+
+fn storage_error(e: impl std::fmt::Display) -> CommandError {
+    CommandError::Storage(e.to_string())
+}
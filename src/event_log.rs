@@ -0,0 +1,173 @@
+//! Persistent event log.
+//!
+//! Wraps the ESP-IDF logger, so every warning and error logged with `log` anywhere in
+//! the firmware also lands in an [`EventLog`], with the module it came from as the
+//! subsystem. [`flush_task`] writes the log to the `spiffs` partition whenever it
+//! changed, at most every few seconds and less often while that fails; a reset in
+//! between only loses those last entries. Messages from the ESP-IDF C code bypass
+//! `log` and are not recorded.
+
+use std::fs;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use esp_idf_svc::log::EspLogger;
+use log::{Level, Log, Metadata, Record};
+
+use crate::recording::MOUNT_POINT;
+use esp_pulser::micros_since_boot;
+use esp_pulser_protocol::*;
+
+const FILE_NAME: &str = "events.bin";
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+/// Longest wait before retrying after storing the log failed
+const MAX_FLUSH_BACKOFF: Duration = Duration::from_secs(300);
+
+static LOGGER: EventLogger = EventLogger {
+    esp: EspLogger::new(),
+    log: Mutex::new(EventLog::new()),
+    boot_count: AtomicU32::new(0),
+    loaded: AtomicBool::new(false),
+    dirty: AtomicBool::new(false),
+};
+
+struct EventLogger {
+    esp: EspLogger,
+    log: Mutex<EventLog>,
+    /// 0 until read from NVS, see [`EventLog::restore`]
+    boot_count: AtomicU32,
+    /// Set once the stored log was read, nothing is written before
+    loaded: AtomicBool,
+    dirty: AtomicBool,
+}
+
+impl EventLogger {
+    /// Nothing may be logged while this is held, as logging takes it too.
+    fn lock(&self) -> MutexGuard<'_, EventLog> {
+        self.log.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Log for EventLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.esp.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.esp.log(record);
+        let severity = match record.level() {
+            Level::Error => Severity::Error,
+            Level::Warn => Severity::Warning,
+            _ => return,
+        };
+        let message = record.args().to_string();
+        self.lock().push(
+            self.boot_count.load(Ordering::Relaxed),
+            micros_since_boot(),
+            severity,
+            subsystem(record.target()),
+            &message,
+        );
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    fn flush(&self) {
+        self.esp.flush();
+    }
+}
+
+/// Install the logger, in place of `EspLogger::initialize_default`.
+pub fn init() {
+    log::set_logger(&LOGGER).unwrap();
+    LOGGER.esp.initialize();
+}
+
+pub fn set_boot_count(boot_count: u32) {
+    LOGGER.boot_count.store(boot_count, Ordering::Relaxed);
+}
+
+/// Read the log kept by earlier boots and start storing it. Needs the `spiffs`
+/// partition to be mounted.
+pub fn load() {
+    let stored = match fs::read(path()) {
+        Ok(bytes) => decode_event_log(&bytes).map_err(anyhow::Error::from),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(EventLog::new()),
+        Err(e) => Err(e.into()),
+    };
+    // A log that cannot be read is replaced, rather than left to block new entries
+    let error = stored.as_ref().err().map(|e| e.to_string());
+    LOGGER.lock().restore(
+        stored.unwrap_or_default(),
+        LOGGER.boot_count.load(Ordering::Relaxed),
+    );
+    LOGGER.loaded.store(true, Ordering::Relaxed);
+    LOGGER.dirty.store(true, Ordering::Relaxed);
+    if let Some(e) = error {
+        log::warn!("Discarding stored event log: {}", e);
+    }
+}
+
+pub fn page(from: u32) -> Vec<LogEntry> {
+    LOGGER.lock().page(from)
+}
+
+/// Drop all entries and store the empty log right away.
+pub fn clear() -> Result<()> {
+    LOGGER.lock().clear();
+    log::info!("Event log cleared");
+    store()
+}
+
+fn store() -> Result<()> {
+    if !LOGGER.loaded.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    LOGGER.dirty.store(false, Ordering::Relaxed);
+    let bytes = encode_event_log(&LOGGER.lock())?;
+    fs::write(path(), bytes)?;
+    Ok(())
+}
+
+pub fn flush_task() {
+    let mut interval = FLUSH_INTERVAL;
+    loop {
+        thread::sleep(interval);
+        if !LOGGER.dirty.load(Ordering::Relaxed) {
+            continue;
+        }
+        match store() {
+            Ok(()) => interval = FLUSH_INTERVAL,
+            Err(e) => {
+                interval = (interval * 2).min(MAX_FLUSH_BACKOFF);
+                // Logged past the event log, which would otherwise record every failed
+                // attempt and have it stored again
+                LOGGER.esp.log(
+                    &Record::builder()
+                        .level(Level::Warn)
+                        .target(module_path!())
+                        .args(format_args!(
+                            "Error storing event log, retrying in {:?}: {:?}",
+                            interval, e
+                        ))
+                        .build(),
+                );
+                LOGGER.dirty.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+fn path() -> String {
+    format!("{MOUNT_POINT}/{FILE_NAME}")
+}
+
+/// Last path segment of a `log` target, the module name for firmware messages.
+fn subsystem(target: &str) -> &str {
+    match target.rsplit("::").next().unwrap_or(target) {
+        "esp_pulser" => "main",
+        module => module,
+    }
+}
@@ -0,0 +1,139 @@
+//! Factory reset.
+//!
+//! Erases the whole `heart_sensor` NVS namespace: settings, the pairing secret, the
+//! release URL and the update history. It runs early at boot, before anything is read
+//! from NVS, either because D2 was held down while the device started or because a
+//! client sent `FactoryReset`, which only leaves a flag behind and restarts.
+//!
+//! The pairing secret is stored in the same namespace, so erasing it unpairs the
+//! device. Nothing else identifies it on the network: the Ethernet MAC is the
+//! compile-time `ETH_MAC` and the hostname is the ESP-IDF default, neither is stored.
+
+use std::ffi::CStr;
+use std::sync::mpsc::Receiver;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use embedded_graphics::pixelcolor::Rgb565;
+use esp_idf_svc::nvs::*;
+use esp_idf_svc::sys::{
+    esp, nvs_close, nvs_commit, nvs_erase_all, nvs_handle_t, nvs_open,
+    nvs_open_mode_t_NVS_READWRITE,
+};
+
+use esp_pulser::TftDisplay;
+use esp_pulser_ui::{
+    draw_message, Button, ButtonEvent, ButtonInput, Gesture, BUTTON_POLL_INTERVAL,
+};
+
+pub const NVS_NAMESPACE: &CStr = c"heart_sensor";
+
+/// How long D2 has to stay pressed at boot
+const BUTTON_HOLD: Duration = Duration::from_secs(5);
+/// How long the confirmation stays on screen before booting on
+const CONFIRMATION_DELAY: Duration = Duration::from_secs(3);
+
+const FACTORY_RESET_KEY: &str = "factory_reset";
+
+/// Have the next boot reset the device.
+pub fn request(nvs: &Mutex<EspNvs<NvsDefault>>) -> Result<()> {
+    nvs.lock().unwrap().set_u8(FACTORY_RESET_KEY, 1)?;
+    Ok(())
+}
+
+pub fn requested(nvs: &Mutex<EspNvs<NvsDefault>>) -> bool {
+    matches!(nvs.lock().unwrap().get_u8(FACTORY_RESET_KEY), Ok(Some(1)))
+}
+
+/// Drop a pending request, so a reset that failed is not retried on every boot.
+pub fn clear_request(nvs: &Mutex<EspNvs<NvsDefault>>) {
+    if let Err(e) = nvs.lock().unwrap().remove(FACTORY_RESET_KEY) {
+        log::warn!("Error clearing factory reset request: {:?}", e);
+    }
+}
+
+/// Whether D2 stays pressed for the whole hold time, counting down on the display
+/// meanwhile. Letting go early cancels the reset. Polls `buttons` itself, as it runs
+/// before they get their own thread, and drops all events.
+pub fn button_held(
+    buttons: &mut ButtonInput,
+    events: &Receiver<ButtonEvent>,
+    display: Option<&mut TftDisplay<'_>>,
+) -> bool {
+    buttons.poll(Instant::now());
+    if !buttons.is_pressed(Button::D2) {
+        return false;
+    }
+    log::info!("D2 pressed at boot, keep holding to factory reset");
+
+    let mut display = display;
+    let started = Instant::now();
+    let mut shown = None;
+    loop {
+        thread::sleep(BUTTON_POLL_INTERVAL);
+        buttons.poll(Instant::now());
+        let held = events.try_iter().any(|event| {
+            matches!(event, ButtonEvent {
+                button: Button::D2,
+                gesture: Gesture::Hold(held),
+            } if held >= BUTTON_HOLD)
+        });
+        if held {
+            return true;
+        }
+        if !buttons.is_pressed(Button::D2) {
+            log::info!("D2 released, skipping factory reset");
+            return false;
+        }
+        let left = BUTTON_HOLD.saturating_sub(started.elapsed()).as_secs() + 1;
+        if shown != Some(left) {
+            shown = Some(left);
+            show(
+                display.as_deref_mut(),
+                Rgb565::BLUE,
+                &format!("Hold D2 to reset\n{left}"),
+            );
+        }
+    }
+}
+
+/// Erase the NVS namespace and show the outcome. Holds the NVS lock throughout, so
+/// nothing is written halfway.
+pub fn run(nvs: &Mutex<EspNvs<NvsDefault>>, display: Option<&mut TftDisplay<'_>>) -> Result<()> {
+    log::warn!("Factory reset, erasing NVS namespace {:?}", NVS_NAMESPACE);
+    let _nvs = nvs.lock().unwrap();
+    let result = erase_namespace();
+
+    match &result {
+        Ok(()) => show(display, Rgb565::GREEN, "Factory reset\ndone"),
+        Err(e) => {
+            log::error!("Factory reset failed: {:?}", e);
+            show(display, Rgb565::RED, "Factory reset\nfailed");
+        }
+    }
+    thread::sleep(CONFIRMATION_DELAY);
+    result
+}
+
+fn erase_namespace() -> Result<()> {
+    let mut handle: nvs_handle_t = 0;
+    unsafe {
+        esp!(nvs_open(
+            NVS_NAMESPACE.as_ptr(),
+            nvs_open_mode_t_NVS_READWRITE,
+            &mut handle
+        ))?;
+        let result = esp!(nvs_erase_all(handle)).and_then(|_| esp!(nvs_commit(handle)));
+        nvs_close(handle);
+        result?;
+    }
+    Ok(())
+}
+
+fn show(display: Option<&mut TftDisplay<'_>>, color: Rgb565, text: &str) {
+    if let Some(display) = display {
+        draw_message(display, color, text).unwrap();
+    }
+}
@@ -25,6 +25,9 @@ use mipidsi::{
     Builder,
 };
 
+/// MAC address assigned to the W5500 ethernet chip.
+pub const ETH_MAC: [u8; 6] = [0x98, 0x76, 0xB6, 0x12, 0xF9, 0x93];
+
 pub struct Board<'d> {
     pub i2c_driver: Mutex<i2c::I2cDriver<'d>>,
     pub spi_driver: Option<Arc<spi::SpiDriver<'d>>>,
@@ -184,7 +187,7 @@ impl<'d> Board<'d> {
             Some(eth_rst),
             SpiEthChipset::W5500,
             20_u32.MHz().into(),
-            Some(&[0x98, 0x76, 0xB6, 0x12, 0xF9, 0x93]),
+            Some(&ETH_MAC),
             None,
             sys_loop.clone(),
         )?)?;
@@ -254,3 +257,16 @@ pub fn connect_eth<T>(eth_async: &mut AsyncEth<EspEth<T>>) -> anyhow::Result<IpI
         Ok(ip_info)
     })
 }
+
+/// Microseconds elapsed since boot, as counted by the ESP high resolution timer.
+pub fn micros_since_boot() -> u64 {
+    unsafe { esp_idf_svc::sys::esp_timer_get_time() as u64 }
+}
+
+/// Random bytes from the hardware RNG. Without the radio running it is only seeded
+/// from the bootloader entropy source, which is good enough for challenge nonces.
+pub fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut buf = [0; N];
+    unsafe { esp_idf_svc::sys::esp_fill_random(buf.as_mut_ptr() as *mut _, N) };
+    buf
+}
@@ -1,63 +1,37 @@
-use std::io::Read;
-use std::net::TcpListener;
-use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
-use std::sync::{Arc, Mutex};
+use std::net::{Ipv4Addr, SocketAddrV4, UdpSocket};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread::{self};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use embedded_graphics::{mono_font::*, pixelcolor::Rgb565, prelude::*, text::*};
 use esp_idf_svc::ipv4::IpInfo;
 use esp_idf_svc::nvs::*;
-use http::Uri;
 use max3010x::Max3010x;
 
 use embedded_hal_bus::i2c::MutexDevice;
-use esp_idf_svc::hal::{prelude::*, reset::restart, task::block_on};
+use esp_idf_svc::hal::prelude::*;
 
 use esp_pulser::*;
+use esp_pulser_protocol::*;
+use esp_pulser_ui::*;
 use pulse_sensor::{SampleData, SAMPLE_RATE};
+use streaming::Subscribers;
+mod commands;
+mod crash;
+mod event_log;
+mod factory_reset;
 mod filters;
+mod menu;
 mod ota;
 mod pulse_sensor;
+mod recording;
+mod self_test;
+mod settings;
+mod streaming;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-#[derive(Debug, Clone, serde::Serialize)]
-struct Status {
-    version: [u8; 3],
-    connected: bool,
-    display_ok: bool,
-    haptic_ok: bool,
-    heart_ok: bool,
-    led_amplitude: u8,
-    haptic_amplitude: u8,
-}
-
-impl Status {
-    fn new() -> Self {
-        let mut version = [0; 3];
-        for (i, v) in VERSION.split('.').take(3).enumerate() {
-            version[i] = v.parse().unwrap_or(0);
-        }
-        Self {
-            version,
-            connected: false,
-            display_ok: false,
-            haptic_ok: false,
-            heart_ok: false,
-            led_amplitude: 0,
-            haptic_amplitude: 0,
-        }
-    }
-}
-
-#[derive(Debug, serde::Serialize)]
-enum Packet {
-    Status(Status),
-    RawHeartRate(f32),
-    Bpm(f32),
-    HeartRate(f32),
-    // Debug((f32, f32, f32)),
+fn firmware_version() -> [u8; 3] {
+    parse_version(VERSION).unwrap_or_default()
 }
 
 fn main() -> anyhow::Result<()> {
@@ -65,18 +39,17 @@ fn main() -> anyhow::Result<()> {
     // implemented by esp-idf-sys might not link properly. See https://github.com/esp-rs/esp-idf-template/issues/71
     esp_idf_svc::sys::link_patches();
 
-    // Bind the log crate to the ESP Logging facilities
-    esp_idf_svc::log::EspLogger::initialize_default();
+    // Bind the log crate to the ESP Logging facilities, keeping warnings and errors in
+    // the event log as well
+    event_log::init();
 
     let nvs_default_partition: EspNvsPartition<NvsDefault> = EspDefaultNvsPartition::take()?;
-    let nvs = EspNvs::new(nvs_default_partition, "heart_sensor", true)?;
-
-    let mut status = Status::new();
-
-    let mut led_amplitude = nvs.get_u8("led_amplitude")?.unwrap_or(35);
-    status.led_amplitude = led_amplitude;
-    let mut haptic_amplitude = nvs.get_u8("haptic_amp")?.unwrap_or(255);
-    status.haptic_amplitude = haptic_amplitude;
+    let nvs = Arc::new(Mutex::new(EspNvs::new(
+        nvs_default_partition,
+        "heart_sensor",
+        true,
+    )?));
+    crash::install_panic_hook();
 
     let peripherals = Peripherals::take()?;
     let sys_loop = esp_idf_svc::eventloop::EspSystemEventLoop::take()?;
@@ -84,6 +57,61 @@ fn main() -> anyhow::Result<()> {
 
     let mut board = Board::new(peripherals, sys_loop.clone(), timer_service.clone());
 
+    let (button_events, events) = mpsc::channel();
+    let (d0_btn, d1_btn, d2_btn) = (board.d0_btn, board.d1_btn, board.d2_btn);
+    let mut buttons = ButtonInput::new(GestureConfig::default(), button_events)
+        // D0 is the BOOT button, which pulls low
+        .with(Button::D0, move || d0_btn.is_low())
+        .with(Button::D1, move || d1_btn.is_high())
+        .with(Button::D2, move || d2_btn.is_high());
+
+    board.backlight.set_high()?;
+    if factory_reset::requested(&nvs)
+        || factory_reset::button_held(&mut buttons, &events, board.display_driver.as_mut())
+    {
+        if let Err(e) = factory_reset::run(&nvs, board.display_driver.as_mut()) {
+            // Boot on with what is stored rather than failing again on every boot
+            log::error!("Booting without factory reset: {:?}", e);
+            factory_reset::clear_request(&nvs);
+        }
+    }
+    board.backlight.set_low()?;
+
+    self_test::record_rollback(&nvs);
+
+    let mut status = Status::new(firmware_version());
+
+    let settings = Arc::new(settings::SettingsStore::load(nvs.clone())?);
+    let (mut led_amplitude, mut haptic_amplitude, mut haptic_effect) =
+        settings.read(|s| (s.sensor.led_amplitude, s.haptic.amplitude, s.haptic.effect));
+    status.led_amplitude = led_amplitude;
+    status.haptic_amplitude = haptic_amplitude;
+
+    status.reset_reason = ota::reset_reason();
+    status.boot_count = {
+        let mut nvs = nvs.lock().unwrap();
+        let count = nvs.get_u32("boot_count")?.unwrap_or(0).wrapping_add(1);
+        nvs.set_u32("boot_count", count)?;
+        count
+    };
+    event_log::set_boot_count(status.boot_count);
+    status.core_dump = crash::core_dump_stored();
+    match crash::report(&nvs) {
+        Ok(report) if report.core_dump_size.is_some() || report.last_panic.is_some() => {
+            log::warn!("Crash report stored: {:?}", report)
+        }
+        Ok(_) => (),
+        Err(e) => log::warn!("Failed to read crash report: {:?}", e),
+    }
+
+    match ota::boot_info(status.reset_reason, status.boot_count) {
+        Ok(info) => {
+            log::info!("Boot info: {:?}", info);
+            status.running_slot = info.running;
+        }
+        Err(e) => log::warn!("Failed to read boot info: {:?}", e),
+    }
+
     status.display_ok = board.display_driver.is_some();
 
     let status: Arc<Mutex<Status>> = Arc::new(Mutex::new(status));
@@ -111,7 +139,7 @@ fn main() -> anyhow::Result<()> {
             rated_voltage: 234,
         })?;
         haptic.init_open_loop_erm()?;
-        haptic.set_single_effect(drv2605::Effect::PulsingStrongOne100)?;
+        haptic.set_single_effect(drv2605_effect(haptic_effect))?;
         anyhow::Ok(haptic)
     })()
     .ok();
@@ -142,45 +170,114 @@ fn main() -> anyhow::Result<()> {
         status.heart_ok = heart.is_some();
     }
 
-    let udp_socket = Arc::new(Mutex::new(UdpSocket::bind(SocketAddrV4::new(
-        Ipv4Addr::new(0, 0, 0, 0),
-        3333,
-    ))?));
+    let udp_socket = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), DATA_PORT))?;
+    let subscribers = Arc::new(Mutex::new(Subscribers::new(udp_socket.try_clone()?)));
 
     {
-        let udp_socket = udp_socket.clone();
-        let status = status.clone();
+        let subscribers = subscribers.clone();
         thread::Builder::new()
-            .stack_size(8 * 1024)
-            .spawn(move || tcp_receiver_task(udp_socket, status, nvs))?;
+            .stack_size(4 * 1024)
+            .spawn(move || streaming::keepalive_task(subscribers, udp_socket))?;
     }
 
-    thread::spawn(move || {
-        block_on(async {
-            loop {
-                board.d1_btn.wait_for_high().await.unwrap();
-                board.backlight.set_high().unwrap();
-                board.d1_btn.wait_for_low().await.unwrap();
-                board.backlight.set_low().unwrap();
-            }
-        })
-    });
+    let health = Arc::new(Mutex::new(SensorHealth::default()));
+
+    match recording::mount() {
+        Ok(()) => event_log::load(),
+        Err(e) => log::warn!("Error mounting SPIFFS, recording unavailable: {:?}", e),
+    }
+    thread::Builder::new()
+        .stack_size(4 * 1024)
+        .spawn(event_log::flush_task)?;
+    let recorder = Arc::new(recording::Recorder::default());
+    let feed = Arc::new(Feed::new(SAMPLE_RATE));
 
     {
-        let udp_socket = udp_socket.clone();
-        let status = status.clone();
-        let ip_info = ip_info.clone();
+        let recorder = recorder.clone();
+        thread::Builder::new()
+            .stack_size(4 * 1024)
+            .spawn(move || recording::flush_task(recorder))?;
+    }
 
+    {
+        let ctx = commands::CommandContext {
+            subscribers: subscribers.clone(),
+            status: status.clone(),
+            health: health.clone(),
+            ip_info: ip_info.clone(),
+            nvs: nvs.clone(),
+            settings: settings.clone(),
+            recorder: recorder.clone(),
+            update: Arc::new(ota::UpdateJob::new(subscribers.clone())),
+        };
         thread::Builder::new()
             .stack_size(8 * 1024)
-            .spawn(move || status_log_thread(udp_socket, board.display_driver, status, ip_info))?;
+            .spawn(move || commands::tcp_receiver_task(ctx))?;
+    }
+
+    {
+        let test = self_test::SelfTest {
+            status: status.clone(),
+            health: health.clone(),
+            ip_info: ip_info.clone(),
+            nvs,
+        };
+        thread::Builder::new()
+            .stack_size(4 * 1024)
+            .spawn(move || self_test::self_test_task(test))?;
+    }
+
+    let menu = Arc::new(Mutex::new(Menu::default()));
+
+    {
+        let controls = menu::Controls {
+            menu: menu.clone(),
+            settings: settings.clone(),
+            status: status.clone(),
+            recorder: recorder.clone(),
+        };
+        let mut backlight = board.backlight;
+        thread::Builder::new()
+            .stack_size(4 * 1024)
+            .spawn(move || buttons.run())?;
+        thread::Builder::new().stack_size(4 * 1024).spawn(move || {
+            menu::input_task(controls, events, |on| {
+                if let Err(e) = backlight.set_level(on.into()) {
+                    log::warn!("Error switching backlight: {:?}", e);
+                }
+            })
+        })?;
+    }
+
+    {
+        let subscribers = subscribers.clone();
+        let status = status.clone();
+        let ip_info = ip_info.clone();
+        let settings = settings.clone();
+        let feed = feed.clone();
+        let recorder = recorder.clone();
+
+        thread::Builder::new().stack_size(8 * 1024).spawn(move || {
+            status_log_thread(
+                subscribers,
+                board.display_driver,
+                status,
+                ip_info,
+                settings,
+                feed,
+                menu,
+                recorder,
+            )
+        })?;
     }
 
-    let mut samples = SampleData::new();
+    let mut detector = settings.read(|s| s.detector);
+    let mut samples = SampleData::new(detector);
     let mut data = [0; 10];
     let interval = Duration::from_micros(1_000_000 / SAMPLE_RATE as u64);
-    let mut counter = 0;
-    let counter_max = SAMPLE_RATE as i32 / 25;
+    let mut sample_index: u64 = 0;
+    let mut finger_detected = false;
+    let mut connected = false;
 
     log::info!("Starting heart rate sensing...");
 
@@ -189,41 +286,107 @@ fn main() -> anyhow::Result<()> {
 
         if let Some(ref mut heart) = heart {
             match heart.read_fifo(&mut data) {
-                Ok(samples_read) if samples_read > 0 => {
-                    counter += 1;
-                    let raw_sample = data[0] as f32;
-                    let (sample, beat_detected) = samples.run(raw_sample);
+                Ok(samples_read) => {
+                    // With a single LED slot, every FIFO entry is one sample
+                    let samples_read = (samples_read as usize).min(data.len());
+                    let read_us = micros_since_boot();
+                    for (i, &raw) in data[..samples_read].iter().enumerate() {
+                        sample_index += 1;
+                        // Samples still queued in the FIFO were taken one period apart
+                        let age = (samples_read - 1 - i) as u64 * interval.as_micros() as u64;
+                        let timestamp_us = read_us.saturating_sub(age);
+                        let stamp = |value| Sample {
+                            index: sample_index,
+                            timestamp_us,
+                            value,
+                        };
+
+                        let raw_sample = raw as f32;
+                        recorder.sample(&stamp(raw_sample));
+                        let (sample, beat) = samples.run(raw_sample);
+                        let beat_detected = beat.is_some();
+                        feed.sample(
+                            sample,
+                            samples.bpm.unwrap_or_default(),
+                            samples.finger_detected(),
+                            beat_detected,
+                        );
 
-                    if beat_detected && haptic.is_some() {
-                        haptic.as_mut().unwrap().set_go(true)?;
-                    }
+                        if samples.finger_detected() != finger_detected {
+                            finger_detected = samples.finger_detected();
+                            let event = match finger_detected {
+                                true => SessionEvent::FingerDetected,
+                                false => SessionEvent::FingerLost,
+                            };
+                            session_event(&recorder, &subscribers, &status, sample_index, event);
+                        }
+
+                        {
+                            let mut health = health.lock().unwrap();
+                            health.samples_read = sample_index;
+                            health.last_sample_us = Some(timestamp_us);
+                            health.finger_detected = samples.finger_detected();
+                        }
 
-                    if counter == counter_max {
-                        counter = 0;
+                        if beat_detected && haptic.is_some() && settings.read(|s| s.haptic.enabled)
+                        {
+                            haptic.as_mut().unwrap().set_go(true)?;
+                        }
+
+                        if let Some(beat) = beat {
+                            let beat = BeatEvent {
+                                sample_index,
+                                timestamp_us: timestamp_us
+                                    .saturating_sub(beat.peak_age.as_micros() as u64),
+                                ibi_ms: beat.ibi_ms,
+                                amplitude: beat.amplitude,
+                                confidence: beat.confidence,
+                            };
+                            recorder.beat(&beat);
+                            send_via_udp(subscribers.clone(), status.clone(), &Packet::Beat(beat));
+                        }
+
+                        // Sample packets are decimated per subscriber, see `Subscribers::send`
                         send_via_udp(
-                            udp_socket.clone(),
+                            subscribers.clone(),
                             status.clone(),
-                            &Packet::RawHeartRate(raw_sample),
+                            &Packet::RawHeartRate(stamp(raw_sample)),
                         );
                         send_via_udp(
-                            udp_socket.clone(),
+                            subscribers.clone(),
                             status.clone(),
-                            &Packet::HeartRate(sample),
+                            &Packet::HeartRate(stamp(sample)),
                         );
-                    }
 
-                    let bpm = samples.bpm.unwrap_or_default();
-                    if beat_detected || bpm == 0.0 {
-                        send_via_udp(udp_socket.clone(), status.clone(), &Packet::Bpm(bpm));
+                        let bpm = samples.bpm.unwrap_or_default();
+                        if beat_detected || bpm == 0.0 {
+                            send_via_udp(
+                                subscribers.clone(),
+                                status.clone(),
+                                &Packet::Bpm(stamp(bpm)),
+                            );
+                        }
                     }
                 }
-                Ok(_) => (),
-                Err(e) => log::error!("Error reading FIFO: {:?}", e),
+                Err(e) => {
+                    log::error!("Error reading FIFO: {:?}", e);
+                    health.lock().unwrap().fifo_errors += 1;
+                }
             }
         }
 
+        if status.lock().unwrap().connected != connected {
+            connected = !connected;
+            let event = match connected {
+                true => SessionEvent::Connected,
+                false => SessionEvent::Disconnected,
+            };
+            session_event(&recorder, &subscribers, &status, sample_index, event);
+        }
+
         {
             let status = status.lock().unwrap();
+
             if status.haptic_amplitude != haptic_amplitude {
                 haptic_amplitude = status.haptic_amplitude;
                 if let Some(haptic) = haptic.as_mut() {
@@ -235,7 +398,7 @@ fn main() -> anyhow::Result<()> {
                         rated_voltage: 234,
                     })?;
                     haptic.init_open_loop_erm()?;
-                    haptic.set_single_effect(drv2605::Effect::PulsingStrongOne100)?;
+                    haptic.set_single_effect(drv2605_effect(haptic_effect))?;
                 }
             }
 
@@ -247,63 +410,113 @@ fn main() -> anyhow::Result<()> {
             }
         }
 
+        let current = settings.read(|s| s.haptic.effect);
+        if current != haptic_effect {
+            haptic_effect = current;
+            if let Some(haptic) = haptic.as_mut() {
+                haptic.set_single_effect(drv2605_effect(haptic_effect))?;
+            }
+        }
+
+        let current = settings.read(|s| s.detector);
+        if current != detector {
+            log::info!("Applying detector settings: {:?}", current);
+            detector = current;
+            samples.set_detector(detector);
+        }
+
         std::thread::sleep(interval.checked_sub(now.elapsed()).unwrap_or_default());
     }
 }
 
+/// How often the status packet is sent
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+/// How often the screen is updated
+const FRAME_INTERVAL: Duration = Duration::from_millis(50);
+
 fn status_log_thread(
-    udp_socket: Arc<Mutex<UdpSocket>>,
+    subscribers: Arc<Mutex<Subscribers>>,
     mut display_driver: Option<TftDisplay<'_>>,
     status: Arc<Mutex<Status>>,
     ip_info: Arc<Mutex<Option<IpInfo>>>,
+    settings: Arc<settings::SettingsStore>,
+    feed: Arc<Feed>,
+    menu: Arc<Mutex<Menu>>,
+    recorder: Arc<recording::Recorder>,
 ) {
-    let mut displayed_ip_info = None::<IpInfo>;
-    let mut first = true;
+    let mut screen = LiveScreen::default();
+    let mut last_status = None::<Instant>;
+
+    if display_driver.is_none() {
+        log::warn!("Display driver not initialized");
+        status.lock().unwrap().display_ok = false;
+    }
 
     loop {
-        thread::sleep(std::time::Duration::from_secs(1));
-        let status_clone = status.lock().unwrap().clone();
-        send_via_udp(
-            udp_socket.clone(),
-            status.clone(),
-            &Packet::Status(status_clone),
-        );
-
-        if display_driver.as_ref().is_some() {
-            let ip_info = ip_info.lock().unwrap();
-            if first || *ip_info != displayed_ip_info {
-                displayed_ip_info = *ip_info;
-            } else {
+        thread::sleep(FRAME_INTERVAL);
+
+        if last_status.is_none_or(|sent| sent.elapsed() >= STATUS_INTERVAL) {
+            last_status = Some(Instant::now());
+            let status_clone = status.lock().unwrap().clone();
+            send_via_udp(
+                subscribers.clone(),
+                status.clone(),
+                &Packet::Status(status_clone),
+            );
+        }
+
+        let Some(display) = display_driver.as_mut() else {
+            continue;
+        };
+        let ip = ip_info.lock().unwrap().map(|info| info.ip);
+
+        // The menu is shown even with the live screen disabled
+        let mut menu = menu.lock().unwrap();
+        if menu.is_open() || menu.needs_redraw() {
+            // Redraw the live screen once the menu is closed
+            screen.invalidate();
+            if menu.needs_redraw() {
+                let info = PageInfo {
+                    status: status.lock().unwrap().clone(),
+                    ip,
+                    mac: ETH_MAC,
+                    subscribers: subscribers.lock().unwrap().list().len(),
+                    recording: recorder.active(),
+                };
+                if let Err(e) = menu.draw(display, &settings.get(), &info) {
+                    log::warn!("Error drawing menu: {:?}", e);
+                }
+            }
+            if menu.is_open() {
                 continue;
             }
+        }
+        drop(menu);
 
-            let display = display_driver.as_mut().unwrap();
-
-            if let Some(displayed_ip_info) = displayed_ip_info {
-                first = false;
-                display.clear(Rgb565::GREEN).unwrap();
-
-                get_styled_text(
-                    &["Connected", &displayed_ip_info.ip.to_string()].join("\n"),
-                    100,
-                    50,
-                )
-                .draw(display)
-                .unwrap();
-            } else {
-                display.clear(Rgb565::RED).unwrap();
-
-                get_styled_text("Unconnected", 100, 50)
-                    .draw(display)
-                    .unwrap();
-            }
-        } else {
-            log::warn!("Display driver not initialized");
-            status.lock().unwrap().display_ok = false;
+        if !settings.read(|s| s.display.enabled) {
+            // Redraw everything once the display is enabled again
+            screen.invalidate();
+            continue;
+        }
+
+        if let Err(e) = screen.draw(display, &feed.frame(ip)) {
+            log::warn!("Error drawing screen: {:?}", e);
+            screen.invalidate();
         }
     }
 }
 
+fn drv2605_effect(effect: HapticEffect) -> drv2605::Effect {
+    match effect {
+        HapticEffect::Pulsing => drv2605::Effect::PulsingStrongOne100,
+        HapticEffect::StrongClick => drv2605::Effect::StrongClick100,
+        HapticEffect::SharpClick => drv2605::Effect::SharpClick100,
+        HapticEffect::SoftBump => drv2605::Effect::SoftBump100,
+        HapticEffect::DoubleClick => drv2605::Effect::DoubleClick100,
+        HapticEffect::Buzz => drv2605::Effect::StrongBuzz100,
+    }
+}
+
 fn eth_reconnect_task(
     eth: Arc<Mutex<Option<EthPeripheral>>>,
     ip_info: Arc<Mutex<Option<IpInfo>>>,
@@ -338,111 +551,30 @@ fn eth_reconnect_task(
     }
 }
 
-fn tcp_receiver_task(
-    udp_socket: Arc<Mutex<UdpSocket>>,
-    status: Arc<Mutex<Status>>,
-    nvs: EspNvs<NvsDefault>,
-) {
-    let tcp_socket =
-        TcpListener::bind(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 12345)).unwrap();
-
-    log::info!("Listening for GUI client...");
-
-    loop {
-        match tcp_socket.accept() {
-            Ok((mut stream, addr)) => {
-                log::info!("Connection from: {:?}", addr);
-
-                let mut buf = [0; 1024];
-                loop {
-                    match stream.read(&mut buf) {
-                        Ok(0) => {
-                            log::info!("Connection closed");
-                            break;
-                        }
-                        Ok(n) => {
-                            log::info!("Received TCP command: {:?}", buf[0]);
-                            match buf[0] {
-                                0 => {
-                                    log::info!("Restarting...");
-                                    restart();
-                                }
-                                1 => {
-                                    log::info!("Attempting update...");
-                                    let data = String::from_utf8(buf[1..n].to_vec()).unwrap();
-                                    log::info!("Update URL: {}", data);
-                                    if let Ok(u) = Uri::try_from(&data) {
-                                        if ota::simple_download_and_update_firmware(u).is_ok() {
-                                            restart();
-                                        }
-                                    }
-                                    let update_url = ota::UPDATE_BIN_URL.replace("TAG", &data);
-                                    if let Ok(u) = Uri::try_from(update_url) {
-                                        if ota::simple_download_and_update_firmware(u).is_ok() {
-                                            restart();
-                                        }
-                                    }
-                                    log::warn!("Invalid URL to download firmware");
-                                }
-                                2 => {
-                                    let led_amplitude = buf[1];
-                                    log::info!("Setting LED amplitude to: {}", led_amplitude);
-                                    status.lock().unwrap().led_amplitude = led_amplitude;
-                                    nvs.set_u8("led_amplitude", led_amplitude).unwrap();
-                                }
-                                3 => {
-                                    let port = u16::from_be_bytes([buf[1], buf[2]]);
-                                    let udp_target = SocketAddr::new(addr.ip(), port);
-                                    log::info!("Connecting to UDP socket at: {}", udp_target);
-                                    udp_socket.lock().unwrap().connect(udp_target).unwrap();
-                                }
-                                4 => {
-                                    let haptic_amplitude = buf[1];
-                                    log::info!("Setting Motor amplitude to: {}", haptic_amplitude);
-                                    status.lock().unwrap().haptic_amplitude = haptic_amplitude;
-                                    nvs.set_u8("haptic_amp", haptic_amplitude).unwrap();
-                                }
-                                _ => {
-                                    log::info!("Unknown command");
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            log::warn!("Error receiving data: {:?}", e);
-                            break;
-                        }
-                    }
-                }
-            }
-            Err(e) => {
-                log::warn!("Error accepting connection: {:?}", e);
-            }
-        }
-    }
-}
-
-fn send_via_udp(udp_socket: Arc<Mutex<UdpSocket>>, status: Arc<Mutex<Status>>, packet: &Packet) {
-    if status.lock().unwrap().connected {
-        match udp_socket
-            .lock()
-            .unwrap()
-            .send(&bincode::serialize(packet).unwrap())
-        {
-            Ok(_) => (),
-            Err(e) => log::warn!("Error sending data: {:?}", e),
-        };
+/// Stream a packet and keep it in the history, which is filled even while the link
+/// is down, so a client connecting right after it comes back gets the last seconds.
+fn send_via_udp(subscribers: Arc<Mutex<Subscribers>>, status: Arc<Mutex<Status>>, packet: &Packet) {
+    let connected = status.lock().unwrap().connected;
+    let mut subscribers = subscribers.lock().unwrap();
+    subscribers.record(packet);
+    if connected {
+        subscribers.send(packet);
     }
 }
 
-fn get_styled_text(text: &str, x: i32, y: i32) -> Text<'_, MonoTextStyle<Rgb565>> {
-    let character_style = MonoTextStyle::new(&ascii::FONT_10X20, Rgb565::WHITE);
-
-    // Create a new text style.
-    let text_style = TextStyleBuilder::new()
-        .alignment(Alignment::Center)
-        .line_height(LineHeight::Percent(100))
-        .build();
-
-    // Create a text at position (20, 30) and draw it using the previously defined style.
-    Text::with_text_style(text, Point::new(x, y), character_style, text_style)
+fn session_event(
+    recorder: &recording::Recorder,
+    subscribers: &Arc<Mutex<Subscribers>>,
+    status: &Arc<Mutex<Status>>,
+    sample_index: u64,
+    event: SessionEvent,
+) {
+    let timestamp_us = micros_since_boot();
+    recorder.event(timestamp_us, event);
+    let event = StampedEvent {
+        sample_index,
+        timestamp_us,
+        event,
+    };
+    send_via_udp(subscribers.clone(), status.clone(), &Packet::Event(event));
 }
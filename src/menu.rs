@@ -0,0 +1,104 @@
+//! Button input driving the on-device menu, see [`Menu`].
+//!
+//! Holding D1 or D2 keeps scrolling or stepping, a double click counts as two clicks
+//! in the menu and as one outside of it, and a long press on D0 closes the menu.
+//! Settings changed in the menu are stored through the same [`SettingsStore`] the
+//! network commands use.
+//!
+//! A button while the backlight is off only turns it on. The menu closes on its own
+//! after a while without input, discarding an unsaved value.
+
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::recording::Recorder;
+use crate::settings::{self, SettingsStore};
+use esp_pulser_protocol::*;
+use esp_pulser_ui::{Action, Button, ButtonEvent, Gesture, Menu};
+
+/// How often timeouts are checked without button events
+const IDLE_INTERVAL: Duration = Duration::from_millis(100);
+/// The menu closes after this long without a button press
+const MENU_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What the input task acts on.
+pub struct Controls {
+    pub menu: Arc<Mutex<Menu>>,
+    pub settings: Arc<SettingsStore>,
+    pub status: Arc<Mutex<Status>>,
+    pub recorder: Arc<Recorder>,
+}
+
+/// Drives the menu with the button events and switches the backlight.
+pub fn input_task(
+    controls: Controls,
+    events: Receiver<ButtonEvent>,
+    mut set_backlight: impl FnMut(bool),
+) {
+    let mut last_event = None::<Instant>;
+    let mut backlight = false;
+    set_backlight(false);
+
+    loop {
+        match events.recv_timeout(IDLE_INTERVAL) {
+            Ok(event) => {
+                last_event = Some(Instant::now());
+                // A button only wakes the screen while it is dark
+                if backlight {
+                    handle(&controls, event);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        let idle = last_event.map_or(Duration::MAX, |at| at.elapsed());
+        let mut menu = controls.menu.lock().unwrap();
+        if idle >= MENU_TIMEOUT {
+            menu.close();
+        }
+        let timeout = controls.settings.read(|s| s.display.backlight_secs);
+        let lit = timeout == 0 || menu.is_open() || idle < Duration::from_secs(timeout as u64);
+        drop(menu);
+        if lit != backlight {
+            backlight = lit;
+            set_backlight(lit);
+        }
+    }
+}
+
+fn handle(controls: &Controls, event: ButtonEvent) {
+    let settings = controls.settings.get();
+    let mut menu = controls.menu.lock().unwrap();
+    let presses = match event.gesture {
+        Gesture::Click => 1,
+        // Outside the menu a double click on D2 would toggle the recording twice
+        Gesture::DoubleClick if event.button != Button::D0 && menu.is_open() => 2,
+        Gesture::DoubleClick => 1,
+        Gesture::LongPress if event.button == Button::D0 => {
+            menu.close();
+            0
+        }
+        // Keep scrolling or stepping while D1 or D2 is held in the menu
+        Gesture::LongPress | Gesture::Hold(_) if menu.is_open() => 1,
+        Gesture::LongPress | Gesture::Hold(_) => 0,
+    };
+    let actions: Vec<_> = (0..presses)
+        .filter_map(|_| menu.press(event.button, &settings))
+        .collect();
+    drop(menu);
+
+    for action in actions {
+        match action {
+            Action::Store(change) => {
+                log::info!("Setting {:?} from the menu", change);
+                match controls.settings.update(|s| change.apply(s)) {
+                    Ok(settings) => settings::apply_to_status(&settings, &controls.status),
+                    Err(e) => log::warn!("Error storing setting: {}", e),
+                }
+            }
+            Action::ToggleRecording => controls.recorder.toggle(),
+        }
+    }
+}
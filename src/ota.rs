@@ -1,39 +1,485 @@
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
 use anyhow::{anyhow, Result};
-use embedded_svc::http::client::Client;
-use embedded_svc::http::Headers;
+use embedded_svc::http::client::Connection;
+use embedded_svc::http::{Headers, Status};
+use embedded_svc::io::Read;
+use esp_idf_svc::hal::reset::restart;
 use esp_idf_svc::http::client::{Configuration, EspHttpConnection};
 use esp_idf_svc::http::Method;
-use esp_idf_svc::ota::{EspFirmwareInfoLoader, EspOta, FirmwareInfo};
-use esp_idf_svc::sys::{ESP_ERR_IMAGE_INVALID, ESP_ERR_INVALID_RESPONSE};
-use http::{header::ACCEPT, Uri};
+use esp_idf_svc::io::EspIOError;
+use esp_idf_svc::ota::{self, EspFirmwareInfoLoader, EspOta, EspOtaUpdate, FirmwareInfo, Slot};
+use esp_idf_svc::sys::{
+    esp, esp_ota_get_next_update_partition, esp_ota_set_boot_partition, esp_reset_reason,
+    esp_reset_reason_t_ESP_RST_BROWNOUT, esp_reset_reason_t_ESP_RST_CPU_LOCKUP,
+    esp_reset_reason_t_ESP_RST_DEEPSLEEP, esp_reset_reason_t_ESP_RST_EFUSE,
+    esp_reset_reason_t_ESP_RST_EXT, esp_reset_reason_t_ESP_RST_INT_WDT,
+    esp_reset_reason_t_ESP_RST_JTAG, esp_reset_reason_t_ESP_RST_PANIC,
+    esp_reset_reason_t_ESP_RST_POWERON, esp_reset_reason_t_ESP_RST_PWR_GLITCH,
+    esp_reset_reason_t_ESP_RST_SDIO, esp_reset_reason_t_ESP_RST_SW,
+    esp_reset_reason_t_ESP_RST_TASK_WDT, esp_reset_reason_t_ESP_RST_USB,
+    esp_reset_reason_t_ESP_RST_WDT, ESP_ERR_HTTP_EAGAIN, ESP_ERR_IMAGE_INVALID, ESP_ERR_TIMEOUT,
+};
+use esp_pulser_protocol::*;
+use http::header::{ACCEPT, RANGE};
+use http::Uri;
+use sha2::{Digest, Sha256};
+
+use crate::firmware_version;
+use crate::streaming::Subscribers;
 
 pub const UPDATE_BIN_URL: &str =
     "https://github.com/krokosik/esp-pulser/releases/download/vTAG/esp-pulser";
+/// Where the manifest of the latest release is published, unless configured otherwise
+pub const DEFAULT_RELEASE_URL: &str =
+    "https://github.com/krokosik/esp-pulser/releases/latest/download";
 
 const FIRMWARE_DOWNLOAD_CHUNK_SIZE: usize = 1024 * 20;
 // Not expect firmware bigger than partition size
 const FIRMWARE_MAX_SIZE: usize = 1_310_720;
 const FIRMWARE_MIN_SIZE: usize = size_of::<FirmwareInfo>() + 1024;
+const SIDECAR_MAX_SIZE: usize = 512;
+/// Gives the final progress packet a chance to go out before restarting
+const RESTART_DELAY: Duration = Duration::from_millis(500);
+
+/// Public half of the key release images are signed with, see `docs/README.md`.
+const OTA_PUBLIC_KEY: [u8; 32] = match decode_hex(include_str!("../keys/ota_signing.pub")) {
+    Some(key) => key,
+    None => panic!("keys/ota_signing.pub must hold 32 hex encoded bytes"),
+};
+
+/// State of the background firmware update, shared by the command server and the
+/// thread doing the work. Every change is streamed to subscribers as a packet.
+pub struct UpdateJob {
+    subscribers: Arc<Mutex<Subscribers>>,
+    status: Mutex<UpdateStatus>,
+    cancelled: AtomicBool,
+}
+
+impl UpdateJob {
+    pub fn new(subscribers: Arc<Mutex<Subscribers>>) -> Self {
+        Self {
+            subscribers,
+            status: Mutex::new(UpdateStatus::default()),
+            cancelled: AtomicBool::new(false),
+        }
+    }
+
+    pub fn status(&self) -> UpdateStatus {
+        self.status.lock().unwrap().clone()
+    }
 
-pub fn simple_download_and_update_firmware(url: Uri) -> Result<()> {
-    let mut client = Client::wrap(EspHttpConnection::new(&Configuration {
-        buffer_size: Some(1024 * 6),
-        buffer_size_tx: Some(1024),
-        use_global_ca_store: true,
-        crt_bundle_attach: Some(esp_idf_svc::sys::esp_crt_bundle_attach),
-        ..Default::default()
-    })?);
-    let headers = [(ACCEPT.as_str(), mime::APPLICATION_OCTET_STREAM.as_ref())];
+    /// Claim the job for a new update, unless one is already running.
+    pub fn begin(&self) -> Result<(), CommandError> {
+        let status = {
+            let mut status = self.status.lock().unwrap();
+            if status.is_active() {
+                return Err(CommandError::UpdateInProgress);
+            }
+            self.cancelled.store(false, Ordering::Relaxed);
+            *status = UpdateStatus {
+                state: UpdateState::Downloading,
+                progress: 0,
+            };
+            status.clone()
+        };
+        self.subscribers
+            .lock()
+            .unwrap()
+            .send(&Packet::Update(status));
+        Ok(())
+    }
+
+    /// Ask the running update to stop at the next chunk.
+    pub fn cancel(&self) -> Result<(), CommandError> {
+        if !self.status.lock().unwrap().is_active() {
+            return Err(CommandError::NoUpdate);
+        }
+        log::info!("Cancelling firmware update");
+        self.cancelled.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Record how the update ended.
+    pub fn finish(&self, result: &Result<()>) {
+        let state = match result {
+            Ok(()) => UpdateState::Done,
+            Err(_) if self.cancelled.load(Ordering::Relaxed) => UpdateState::Cancelled,
+            Err(e) if matches!(e.downcast_ref(), Some(VersionError::SameVersion(_))) => {
+                UpdateState::UpToDate
+            }
+            Err(e) => UpdateState::Failed(e.to_string()),
+        };
+        self.set(state);
+    }
+
+    fn set(&self, state: UpdateState) {
+        let progress = self.status.lock().unwrap().progress;
+        self.update(UpdateStatus { state, progress });
+    }
+
+    /// Report transfer progress, failing once the update has been cancelled.
+    fn transferred(&self, written: usize, total: usize) -> Result<(), DownloadError> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Err(DownloadError::Cancelled);
+        }
+        self.update(UpdateStatus {
+            state: UpdateState::Downloading,
+            progress: (written * 100 / total.max(1)) as u8,
+        });
+        Ok(())
+    }
+
+    fn update(&self, status: UpdateStatus) {
+        {
+            let mut current = self.status.lock().unwrap();
+            if *current == status {
+                return;
+            }
+            *current = status.clone();
+        }
+        log::info!("Firmware update: {:?}", status);
+        self.subscribers
+            .lock()
+            .unwrap()
+            .send(&Packet::Update(status));
+    }
+}
+
+/// Resolve the argument of an update command, either a full URL or a release tag.
+pub fn update_url(url_or_tag: &str) -> Option<Uri> {
+    match Uri::try_from(url_or_tag) {
+        Ok(url) if url.scheme().is_some() => Some(url),
+        _ => Uri::try_from(UPDATE_BIN_URL.replace("TAG", url_or_tag)).ok(),
+    }
+}
+
+/// Download and install an image in a background thread, restarting into it once done.
+/// Unless `force` is set, only images newer than the running firmware are installed.
+/// Timeouts and retries are taken from `network`.
+pub fn start_download(
+    job: Arc<UpdateJob>,
+    url: Uri,
+    force: bool,
+    network: &NetworkSettings,
+) -> Result<(), CommandError> {
+    job.begin()?;
+    let job_clone = job.clone();
+    let config = DownloadConfig {
+        chunk_len: FIRMWARE_DOWNLOAD_CHUNK_SIZE,
+        ..network.download_config()
+    };
+    let spawned = thread::Builder::new().stack_size(12 * 1024).spawn(move || {
+        let result = download_and_update_firmware(&job_clone, url, force, &config);
+        job_clone.finish(&result);
+        match result {
+            Ok(()) => {
+                log::info!("Firmware update complete, restarting...");
+                thread::sleep(RESTART_DELAY);
+                restart();
+            }
+            Err(e) => log::warn!("Firmware update failed: {}", e),
+        }
+    });
+    if let Err(e) = spawned {
+        let e = anyhow!(e);
+        let reason = e.to_string();
+        job.finish(&Err(e));
+        return Err(CommandError::Update(reason));
+    }
+    Ok(())
+}
+
+fn download_and_update_firmware(
+    job: &UpdateJob,
+    url: Uri,
+    force: bool,
+    config: &DownloadConfig,
+) -> Result<()> {
+    let mut client = EspClient {
+        timeout: config.timeout,
+        conn: None,
+    };
     let surl = url.to_string();
-    let request = client
-        .request(Method::Get, &surl, &headers)
-        .map_err(|e| e.0)?;
-    let mut response = request.submit().map_err(|e| e.0)?;
-    if response.status() != 200 {
-        log::info!("Bad HTTP response: {}", response.status());
-        return Err(anyhow!(ESP_ERR_INVALID_RESPONSE));
-    }
-    let file_size = response.content_len().unwrap_or(0) as usize;
+
+    let digest = fetch_small(&mut client, &format!("{surl}.sha256"), config)
+        .ok()
+        .and_then(|text| parse_image_digest(&String::from_utf8_lossy(&text)))
+        .ok_or_else(|| anyhow!("No published SHA-256 digest for {surl}"))?;
+    let signature = fetch_small(&mut client, &format!("{surl}.sig"), config)
+        .map_err(|e| anyhow!("No published signature for {surl}: {e}"))?;
+    verify_signature(&digest, &signature)?;
+
+    let mut ota = EspOta::new()?;
+    let mut sink = OtaSink {
+        job,
+        writer: OtaWriter::new(&mut ota, force),
+        hasher: Sha256::new(),
+        total: 0,
+        written: 0,
+        refused: None,
+    };
+    let dl_result = download(&mut client, &surl, config, &mut sink);
+    let OtaSink {
+        writer,
+        hasher,
+        refused,
+        ..
+    } = sink;
+
+    let dl_result = dl_result
+        .map_err(|e| match refused {
+            Some(refused) => anyhow!(refused),
+            None => anyhow!(e),
+        })
+        .and_then(|_| {
+            job.set(UpdateState::Verifying);
+            check_digest(hasher, &digest)
+        });
+    if dl_result.is_ok() {
+        job.set(UpdateState::Writing);
+    }
+    writer.finish(dl_result)
+}
+
+/// Writes an image into the update slot. The slot is only erased once the header at
+/// the start of the image is buffered and checked, so an image that is refused leaves
+/// the firmware in the inactive slot intact for a rollback.
+struct OtaWriter<'a> {
+    /// Taken once the update starts
+    ota: Option<&'a mut EspOta>,
+    work: Option<EspOtaUpdate<'a>>,
+    header: Vec<u8>,
+    force: bool,
+}
+
+impl<'a> OtaWriter<'a> {
+    fn new(ota: &'a mut EspOta, force: bool) -> Self {
+        Self {
+            ota: Some(ota),
+            work: None,
+            header: Vec::new(),
+            force,
+        }
+    }
+
+    /// Write the next bytes of the image. Refusing the image for its version fails
+    /// with a [`VersionError`].
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        if let Some(work) = &mut self.work {
+            return work.write(data).map_err(|e| {
+                log::error!("Failed to write to OTA. {e}");
+                anyhow!(e)
+            });
+        }
+
+        self.header.extend_from_slice(data);
+        if self.header.len() < FIRMWARE_MIN_SIZE {
+            return Ok(());
+        }
+        match get_firmware_info(&self.header) {
+            Ok(info) => log::info!("Firmware to be installed: {info:?}"),
+            Err(e) => {
+                log::error!("Failed to get firmware info from the image!");
+                return Err(e);
+            }
+        }
+        check_image(&self.header, self.force)?;
+
+        let ota = self.ota.take().expect("update already started");
+        let work = self.work.insert(ota.initiate_update()?);
+        work.write(&self.header).map_err(|e| {
+            log::error!("Failed to write to OTA. {e}");
+            anyhow!(e)
+        })?;
+        self.header = Vec::new();
+        Ok(())
+    }
+
+    /// Mark the image bootable if `result` is a success, otherwise abort the update.
+    fn finish(self, result: Result<()>) -> Result<()> {
+        let Some(work) = self.work else {
+            // The slot was never touched
+            return result.and(Err(anyhow!("Image ended within its header")));
+        };
+        match result {
+            Ok(()) => work.complete().map_err(|e| anyhow!(e)),
+            Err(e) => {
+                work.abort()?;
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Writes a downloaded image into the update slot, hashing it along the way.
+struct OtaSink<'a> {
+    job: &'a UpdateJob,
+    writer: OtaWriter<'a>,
+    hasher: Sha256,
+    total: usize,
+    written: usize,
+    /// Why the image was not installed, if its version was the problem
+    refused: Option<VersionError>,
+}
+
+impl DownloadSink for OtaSink<'_> {
+    fn begin(&mut self, total: usize) -> Result<(), DownloadError> {
+        check_firmware_size(total).map_err(|e| DownloadError::Rejected(e.to_string()))?;
+        self.total = total;
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), DownloadError> {
+        if let Err(e) = self.writer.write(data) {
+            let reason = e.to_string();
+            self.refused = e.downcast().ok();
+            return Err(DownloadError::Rejected(reason));
+        }
+        self.hasher.update(data);
+        self.written += data.len();
+        self.job.transferred(self.written, self.total)
+    }
+
+    fn retrying(&mut self, error: &DownloadError, backoff: Duration) -> Result<(), DownloadError> {
+        log::warn!(
+            "Firmware download interrupted after {} bytes: {}, retrying in {:?}",
+            self.written,
+            error,
+            backoff
+        );
+        self.job.transferred(self.written, self.total)
+    }
+}
+
+/// Fetch the release manifest from `base_url` and compare it with the running firmware.
+pub fn check_for_update(base_url: &str, network: &NetworkSettings) -> Result<UpdateCheck> {
+    let url = format!(
+        "{}/{}",
+        base_url.trim_end_matches('/'),
+        RELEASE_MANIFEST_NAME
+    );
+    let config = network.download_config();
+    let mut client = EspClient {
+        timeout: config.timeout,
+        conn: None,
+    };
+    let text = fetch_small(&mut client, &url, &config)?;
+    let manifest = parse_release_manifest(&String::from_utf8_lossy(&text))
+        .ok_or_else(|| anyhow!("Invalid release manifest at {url}"))?;
+    let running = firmware_version();
+    Ok(UpdateCheck {
+        running,
+        latest: manifest.version,
+        image: manifest.image,
+        available: manifest.version > running,
+    })
+}
+
+/// Refuses files too large to be a digest, signature or manifest.
+struct SmallFile(Vec<u8>);
+
+impl DownloadSink for SmallFile {
+    fn begin(&mut self, total: usize) -> Result<(), DownloadError> {
+        if total > SIDECAR_MAX_SIZE {
+            return Err(DownloadError::Rejected(format!(
+                "{total} bytes is too large"
+            )));
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), DownloadError> {
+        self.0.extend_from_slice(data);
+        Ok(())
+    }
+}
+
+/// Download a small file, such as the digest or signature published with an image.
+fn fetch_small(
+    client: &mut EspClient,
+    url: &str,
+    config: &DownloadConfig,
+) -> Result<Vec<u8>, DownloadError> {
+    let mut file = SmallFile(Vec::new());
+    download(client, url, config, &mut file)?;
+    Ok(file.0)
+}
+
+/// [`HttpClient`] on top of the ESP-IDF client. Every request gets a new connection,
+/// so that a retry never reuses a broken one.
+struct EspClient {
+    timeout: Duration,
+    conn: Option<EspHttpConnection>,
+}
+
+impl HttpClient for EspClient {
+    fn get(&mut self, url: &str, offset: usize) -> Result<ResponseHead, DownloadError> {
+        self.conn = None;
+        let conn = EspHttpConnection::new(&Configuration {
+            buffer_size: Some(1024 * 6),
+            buffer_size_tx: Some(1024),
+            timeout: Some(self.timeout),
+            use_global_ca_store: true,
+            crt_bundle_attach: Some(esp_idf_svc::sys::esp_crt_bundle_attach),
+            ..Default::default()
+        })
+        .map_err(|e| DownloadError::Connect(e.to_string()))?;
+        let conn = self.conn.insert(conn);
+
+        let range = format!("bytes={offset}-");
+        let mut headers = vec![(ACCEPT.as_str(), mime::APPLICATION_OCTET_STREAM.as_ref())];
+        if offset > 0 {
+            headers.push((RANGE.as_str(), range.as_str()));
+        }
+        conn.initiate_request(Method::Get, url, &headers)
+            .map_err(|e| DownloadError::Connect(e.to_string()))?;
+        conn.initiate_response().map_err(io_error)?;
+
+        Ok(ResponseHead {
+            status: conn.status(),
+            content_length: conn.content_len().map(|len| len as usize),
+            content_range: conn.header("Content-Range").map(str::to_string),
+        })
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, DownloadError> {
+        match self.conn.as_mut() {
+            Some(conn) => conn.read(buf).map_err(io_error),
+            None => Err(DownloadError::Interrupted("no request sent".into())),
+        }
+    }
+}
+
+fn io_error(e: EspIOError) -> DownloadError {
+    match e.0.code() as u32 {
+        ESP_ERR_HTTP_EAGAIN | ESP_ERR_TIMEOUT => DownloadError::Timeout,
+        _ => DownloadError::Interrupted(e.to_string()),
+    }
+}
+
+/// Check that the digest of an image was signed with the release key.
+pub fn verify_signature(digest: &ImageDigest, signature: &[u8]) -> Result<()> {
+    if !verify_image_signature(&OTA_PUBLIC_KEY, digest, signature) {
+        log::error!("Firmware signature is invalid!");
+        return Err(anyhow!("Invalid firmware signature"));
+    }
+    Ok(())
+}
+
+fn check_digest(hasher: Sha256, digest: &ImageDigest) -> Result<()> {
+    if hasher.finalize().as_slice() != digest {
+        log::error!("Firmware SHA-256 digest does not match the published one!");
+        return Err(anyhow!("SHA-256 digest mismatch"));
+    }
+    Ok(())
+}
+
+pub fn check_firmware_size(file_size: usize) -> Result<()> {
     if file_size <= FIRMWARE_MIN_SIZE {
         log::info!(
             "File size is {file_size}, too small to be a firmware! No need to proceed further."
@@ -44,42 +490,82 @@ pub fn simple_download_and_update_firmware(url: Uri) -> Result<()> {
         log::info!("File is too big ({file_size} bytes).");
         return Err(anyhow!(ESP_ERR_IMAGE_INVALID));
     }
+    Ok(())
+}
+
+/// Supplies a firmware image pushed by a client, see [`upload_firmware`].
+pub trait FirmwareSource {
+    /// Next chunk of the image, given the number of bytes written so far, or `None`
+    /// once the client declares the image complete.
+    fn next_chunk(&mut self, written: usize) -> Result<Option<Vec<u8>>>;
+    /// Called after each chunk has been written to flash.
+    fn progress(&mut self, written: usize);
+}
+
+/// Write an image of `size` bytes from `source` into the update slot. The image is
+/// only marked bootable if its size and SHA-256 digest match the announced ones and
+/// the digest is signed with the release key. Otherwise, or if the source fails, the
+/// update is aborted, as it is for images that are not newer than the running firmware
+/// unless `force` is set. Progress is reported through `job`, which the caller must
+/// have claimed with [`UpdateJob::begin`].
+pub fn upload_firmware(
+    job: &UpdateJob,
+    size: usize,
+    sha256: &ImageDigest,
+    signature: &[u8],
+    force: bool,
+    source: &mut impl FirmwareSource,
+) -> Result<()> {
+    check_firmware_size(size)?;
+    verify_signature(sha256, signature)?;
     let mut ota = EspOta::new()?;
-    let mut work = ota.initiate_update()?;
-    let mut buff = vec![0; FIRMWARE_DOWNLOAD_CHUNK_SIZE];
-    let mut total_read_len: usize = 0;
-    let mut got_info = false;
-    let dl_result = loop {
-        let n = response.read(&mut buff).unwrap_or_default();
-        total_read_len += n;
-        if !got_info {
-            match get_firmware_info(&buff[..n]) {
-                Ok(info) => log::info!("Firmware to be downloaded: {info:?}"),
-                Err(e) => {
-                    log::error!("Failed to get firmware info from downloaded bytes!");
-                    break Err(e);
-                }
-            };
-            got_info = true;
+    let mut writer = OtaWriter::new(&mut ota, force);
+    let mut hasher = Sha256::new();
+    let mut written = 0;
+
+    let ul_result = loop {
+        let chunk = match source.next_chunk(written) {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break Ok(()),
+            Err(e) => break Err(e),
+        };
+        if written + chunk.len() > size {
+            break Err(anyhow!("Received more than the announced {size} bytes"));
         }
-        if n > 0 {
-            if let Err(e) = work.write(&buff[..n]) {
-                log::error!("Failed to write to OTA. {e}");
-                break Err(anyhow!(e));
-            }
+        if let Err(e) = writer.write(&chunk) {
+            break Err(e);
         }
-        if total_read_len >= file_size {
-            break Ok(());
+        hasher.update(&chunk);
+        written += chunk.len();
+        source.progress(written);
+        if let Err(e) = job.transferred(written, size) {
+            break Err(e.into());
         }
     };
-    if dl_result.is_err() {
-        return work.abort().map_err(|e| anyhow!(e));
+
+    let ul_result = ul_result.and_then(|_| {
+        if written != size {
+            return Err(anyhow!("Got {written} of the announced {size} bytes"));
+        }
+        job.set(UpdateState::Verifying);
+        check_digest(hasher, sha256)
+    });
+    if ul_result.is_ok() {
+        job.set(UpdateState::Writing);
     }
-    if total_read_len < file_size {
-        log::error!("Supposed to download {file_size} bytes, but we could only get {total_read_len}. May be network error?");
-        return work.abort().map_err(|e| anyhow!(e));
+    writer.finish(ul_result)
+}
+
+/// Compare the version in the header of an image with the running firmware.
+fn check_image(buff: &[u8], force: bool) -> Result<(), VersionError> {
+    let version = get_firmware_info(buff)
+        .map(|info| info.version.as_str().to_string())
+        .unwrap_or_default();
+    let result = check_image_version(firmware_version(), &version, force);
+    if let Err(e) = &result {
+        log::warn!("Not installing firmware: {}", e);
     }
-    work.complete().map_err(|e| anyhow!(e))
+    result
 }
 
 fn get_firmware_info(buff: &[u8]) -> Result<FirmwareInfo> {
@@ -87,3 +573,101 @@ fn get_firmware_info(buff: &[u8]) -> Result<FirmwareInfo> {
     loader.load(buff)?;
     loader.get_info().map_err(|e| anyhow!(e))
 }
+
+pub fn partitions_info() -> Result<PartitionsInfo> {
+    let ota = EspOta::new()?;
+    Ok(PartitionsInfo {
+        running: slot_info(ota.get_running_slot()?),
+        update: slot_info(ota.get_update_slot()?),
+    })
+}
+
+pub fn boot_info(reset_reason: ResetReason, boot_count: u32) -> Result<BootInfo> {
+    let ota = EspOta::new()?;
+    let running = slot_info(ota.get_running_slot()?);
+    let mut slots = vec![running.clone(), slot_info(ota.get_update_slot()?)];
+    slots.sort_by(|a, b| a.label.cmp(&b.label));
+    Ok(BootInfo {
+        running: running.label,
+        next_boot: ota.get_boot_slot()?.label.as_str().to_string(),
+        slots,
+        reset_reason,
+        boot_count,
+    })
+}
+
+/// Make the other OTA slot the boot partition. Slots without firmware, or with
+/// firmware that failed its self-test, are refused.
+pub fn switch_boot_slot() -> Result<()> {
+    let target = EspOta::new()?.get_update_slot()?;
+    let label = target.label.as_str();
+    if target.firmware.is_none() {
+        return Err(anyhow!("Slot {label} holds no firmware"));
+    }
+    if matches!(target.state, ota::SlotState::Invalid) {
+        return Err(anyhow!("Firmware in slot {label} is marked invalid"));
+    }
+    log::info!("Switching boot partition to {}", label);
+    esp!(unsafe { esp_ota_set_boot_partition(esp_ota_get_next_update_partition(ptr::null())) })?;
+    Ok(())
+}
+
+#[allow(non_upper_case_globals)]
+pub fn reset_reason() -> ResetReason {
+    match unsafe { esp_reset_reason() } {
+        esp_reset_reason_t_ESP_RST_POWERON => ResetReason::PowerOn,
+        esp_reset_reason_t_ESP_RST_EXT => ResetReason::ExternalPin,
+        esp_reset_reason_t_ESP_RST_SW => ResetReason::Software,
+        esp_reset_reason_t_ESP_RST_PANIC => ResetReason::Panic,
+        esp_reset_reason_t_ESP_RST_INT_WDT => ResetReason::InterruptWatchdog,
+        esp_reset_reason_t_ESP_RST_TASK_WDT => ResetReason::TaskWatchdog,
+        esp_reset_reason_t_ESP_RST_WDT => ResetReason::Watchdog,
+        esp_reset_reason_t_ESP_RST_DEEPSLEEP => ResetReason::DeepSleep,
+        esp_reset_reason_t_ESP_RST_BROWNOUT => ResetReason::Brownout,
+        esp_reset_reason_t_ESP_RST_SDIO => ResetReason::Sdio,
+        esp_reset_reason_t_ESP_RST_USB => ResetReason::Usb,
+        esp_reset_reason_t_ESP_RST_JTAG => ResetReason::Jtag,
+        esp_reset_reason_t_ESP_RST_EFUSE => ResetReason::Efuse,
+        esp_reset_reason_t_ESP_RST_PWR_GLITCH => ResetReason::PowerGlitch,
+        esp_reset_reason_t_ESP_RST_CPU_LOCKUP => ResetReason::CpuLockup,
+        _ => ResetReason::Unknown,
+    }
+}
+
+/// Whether the running image was just installed and still has to pass its self-test.
+pub fn running_slot_pending_verify() -> Result<bool> {
+    let slot = EspOta::new()?.get_running_slot()?;
+    Ok(matches!(slot.state, ota::SlotState::Unverified))
+}
+
+pub fn mark_running_slot_valid() -> Result<()> {
+    EspOta::new()?.mark_running_slot_valid()?;
+    Ok(())
+}
+
+/// Mark the running image invalid and reboot into the previous one. Only returns if
+/// that failed.
+pub fn roll_back() -> anyhow::Error {
+    match EspOta::new() {
+        Ok(mut ota) => anyhow!(ota.mark_running_slot_invalid_and_reboot()),
+        Err(e) => anyhow!(e),
+    }
+}
+
+/// The slot the bootloader last rolled back from, if any.
+pub fn last_invalid_slot() -> Result<Option<SlotInfo>> {
+    Ok(EspOta::new()?.get_last_invalid_slot()?.map(slot_info))
+}
+
+fn slot_info(slot: Slot) -> SlotInfo {
+    SlotInfo {
+        label: slot.label.as_str().to_string(),
+        state: match slot.state {
+            ota::SlotState::Valid => SlotState::Valid,
+            ota::SlotState::Invalid => SlotState::Invalid,
+            ota::SlotState::Unverified => SlotState::Unverified,
+            _ => SlotState::Unknown,
+        },
+        version: slot.firmware.map(|info| info.version.as_str().to_string()),
+    }
+}
@@ -1,26 +1,47 @@
-use std::time::Instant;
+use std::time::Duration;
 
 use crate::filters::*;
+use esp_pulser_protocol::DetectorSettings;
 
 pub const SAMPLE_RATE: f32 = 400.0;
 
-const FINGER_THRESHOLD: f32 = 100_000.0;
-const FINGER_COOLDOWN_MS: u32 = 500;
+pub const LP_CUT_OFF: f32 = 5.0;
+pub const HP_CUT_OFF: f32 = 0.5;
+
+/// Details of a detected heartbeat.
+#[derive(Debug, Clone, Copy)]
+pub struct Beat {
+    /// Time between the previous and this beat's peak, in milliseconds
+    pub ibi_ms: f32,
+    /// Peak to trough height of the filtered pulse wave
+    pub amplitude: f32,
+    /// Agreement of this interval with the previous one, from 0.0 to 1.0
+    pub confidence: f32,
+    /// How long ago the peak happened, relative to the sample that confirmed it
+    pub peak_age: Duration,
+}
 
-const EDGE_THRESHOLD: f32 = -2000.0;
+/// Heartbeat detection on the raw samples. All times are counted in samples, so they
+/// do not depend on when a batch of samples read from the FIFO gets processed.
+pub struct SampleData {
+    detector: DetectorSettings,
 
-const LP_CUT_OFF: f32 = 5.0;
-const HP_CUT_OFF: f32 = 0.5;
+    /// Samples processed so far
+    samples: u64,
 
-pub struct SampleData {
-    pub last_heartbeat: Option<Instant>,
+    /// Sample at which the peak of the last beat was crossed
+    pub last_heartbeat: Option<u64>,
 
-    fingerprint_timestamp: Instant,
+    /// Sample from which on the reading stayed above the finger threshold
+    finger_since: u64,
     finger_detected: bool,
 
     pub last_diff: Option<f32>,
     crossed: bool,
-    crossed_time: Option<Instant>,
+    crossed_at: Option<u64>,
+    peak_value: f32,
+    trough_value: f32,
+    last_ibi_ms: Option<f32>,
 
     hp_filter: HighPassFilter,
     lp_filter: LowPassFilter,
@@ -30,14 +51,20 @@ pub struct SampleData {
 }
 
 impl SampleData {
-    pub fn new() -> Self {
+    pub fn new(detector: DetectorSettings) -> Self {
         SampleData {
+            detector,
+
+            samples: 0,
             last_heartbeat: None,
-            fingerprint_timestamp: Instant::now(),
+            finger_since: 0,
             finger_detected: false,
             last_diff: None,
             crossed: false,
-            crossed_time: None,
+            crossed_at: None,
+            peak_value: 0.0,
+            trough_value: 0.0,
+            last_ibi_ms: None,
 
             hp_filter: HighPassFilter::new(HP_CUT_OFF, SAMPLE_RATE),
             lp_filter: LowPassFilter::new(LP_CUT_OFF, SAMPLE_RATE),
@@ -47,12 +74,14 @@ impl SampleData {
         }
     }
 
-    pub fn run(&mut self, sample: f32) -> (f32, bool) {
+    pub fn run(&mut self, sample: f32) -> (f32, Option<Beat>) {
         let mut result_sample = sample;
-        let mut beat_detected = false;
+        let mut beat = None;
+        self.samples += 1;
 
-        if sample > FINGER_THRESHOLD {
-            if self.fingerprint_timestamp.elapsed().as_millis() > FINGER_COOLDOWN_MS as u128 {
+        if sample > self.detector.finger_threshold {
+            if self.since(self.finger_since).as_millis() > self.detector.finger_cooldown_ms as u128
+            {
                 self.finger_detected = true;
             }
         } else {
@@ -72,44 +101,78 @@ impl SampleData {
 
                 if last_diff > 0.0 && diff < 0.0 {
                     self.crossed = true;
-                    self.crossed_time = Some(Instant::now());
+                    self.crossed_at = Some(self.samples);
+                    self.peak_value = sample;
+                    self.trough_value = sample;
+                }
+
+                if self.crossed {
+                    self.trough_value = self.trough_value.min(sample);
                 }
 
                 if diff > 0.0 {
                     self.crossed = false;
                 }
 
-                if self.crossed && diff < EDGE_THRESHOLD {
+                if self.crossed && diff < self.detector.edge_threshold {
                     if self.last_heartbeat.is_some_and(|last_heartbeat| {
-                        self.crossed_time.is_some_and(|crossed_time| {
-                            crossed_time.duration_since(last_heartbeat).as_millis() > 300
+                        self.crossed_at.is_some_and(|crossed_at| {
+                            sample_time(crossed_at - last_heartbeat).as_millis()
+                                > self.detector.min_ibi_ms as u128
                         })
                     }) {
-                        let bpm = 60_000
-                            / self
-                                .crossed_time
-                                .unwrap()
-                                .duration_since(self.last_heartbeat.unwrap())
-                                .as_millis();
-
-                        let bpm = bpm as f32;
+                        let crossed_at = self.crossed_at.unwrap();
+                        let ibi = sample_time(crossed_at - self.last_heartbeat.unwrap());
+                        let bpm = (60_000 / ibi.as_millis()) as f32;
 
                         if bpm > 30.0 && bpm < 200.0 {
-                            beat_detected = true;
+                            let ibi_ms = ibi.as_secs_f32() * 1000.0;
+                            beat = Some(Beat {
+                                ibi_ms,
+                                amplitude: self.peak_value - self.trough_value,
+                                confidence: self.confidence(ibi_ms),
+                                peak_age: self.since(crossed_at),
+                            });
+                            self.last_ibi_ms = Some(ibi_ms);
                             self.bpm = Some(bpm);
                         } else {
+                            self.last_ibi_ms = None;
                             self.bpm = None;
                         }
                     }
                     self.crossed = false;
-                    self.last_heartbeat = self.crossed_time;
+                    self.last_heartbeat = self.crossed_at;
                 }
             }
 
             self.last_diff = diff;
         }
 
-        (result_sample, beat_detected)
+        (result_sample, beat)
+    }
+
+    /// Switch to new detector settings, starting over with finger detection.
+    pub fn set_detector(&mut self, detector: DetectorSettings) {
+        self.detector = detector;
+        self.reset_state();
+    }
+
+    pub fn finger_detected(&self) -> bool {
+        self.finger_detected
+    }
+
+    /// Time from the given sample to the current one.
+    fn since(&self, sample: u64) -> Duration {
+        sample_time(self.samples - sample)
+    }
+
+    /// Rate how well an interval agrees with the previous one. The first beat after
+    /// finger placement has nothing to compare against, so it only gets half confidence.
+    fn confidence(&self, ibi_ms: f32) -> f32 {
+        match self.last_ibi_ms {
+            None => 0.5,
+            Some(last_ibi_ms) => (1.0 - (ibi_ms - last_ibi_ms).abs() / last_ibi_ms).clamp(0.0, 1.0),
+        }
     }
 
     fn reset_state(&mut self) {
@@ -118,11 +181,19 @@ impl SampleData {
         self.differentiator.reset_state();
 
         self.last_heartbeat = None;
-        self.fingerprint_timestamp = Instant::now();
+        self.finger_since = self.samples;
         self.finger_detected = false;
         self.last_diff = None;
         self.crossed = false;
-        self.crossed_time = None;
+        self.crossed_at = None;
+        self.peak_value = 0.0;
+        self.trough_value = 0.0;
+        self.last_ibi_ms = None;
         self.bpm = None;
     }
 }
+
+/// Time spanned by the given number of sample periods.
+fn sample_time(samples: u64) -> Duration {
+    Duration::from_secs_f64(samples as f64 / SAMPLE_RATE as f64)
+}
@@ -0,0 +1,296 @@
+//! Session recording to the `spiffs` partition.
+//!
+//! While a recording runs, the sampling loop encodes raw samples, beats and events into
+//! a buffer in RAM, see [`RecordingEncoder`], and [`flush_task`] appends that buffer to
+//! the file once a second. Recording does not depend on the network at all, so a
+//! session carries on through link outages, which show up as events in the file. After
+//! a reset the file simply ends at the last flush.
+
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::ptr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use esp_idf_svc::sys::{esp, esp_spiffs_info, esp_vfs_spiffs_conf_t, esp_vfs_spiffs_register};
+
+use crate::firmware_version;
+use crate::pulse_sensor::SAMPLE_RATE;
+use esp_pulser::micros_since_boot;
+use esp_pulser_protocol::*;
+
+pub const MOUNT_POINT: &str = "/spiffs";
+const FILE_PREFIX: &str = "rec";
+const FILE_SUFFIX: &str = ".bin";
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+/// Records are dropped beyond this if the flash cannot keep up
+const MAX_PENDING_LEN: usize = 32 * 1024;
+/// Recording stops before SPIFFS fills up completely, as it slows to a crawl then
+const MIN_FREE_BYTES: usize = 16 * 1024;
+
+/// Mount the `spiffs` partition, formatting it on first use.
+pub fn mount() -> Result<()> {
+    let conf = esp_vfs_spiffs_conf_t {
+        base_path: c"/spiffs".as_ptr(),
+        partition_label: ptr::null(),
+        max_files: 4,
+        format_if_mount_failed: true,
+    };
+    esp!(unsafe { esp_vfs_spiffs_register(&conf) })?;
+    let (total, used) = usage()?;
+    log::info!("SPIFFS mounted, {} of {} bytes used", used, total);
+    Ok(())
+}
+
+fn usage() -> Result<(usize, usize)> {
+    let (mut total, mut used) = (0, 0);
+    esp!(unsafe { esp_spiffs_info(ptr::null(), &mut total, &mut used) })?;
+    Ok((total, used))
+}
+
+/// The recording being written, as seen by the sampling loop.
+struct Active {
+    name: String,
+    encoder: RecordingEncoder,
+    /// Encoded records not yet written to the file
+    pending: Vec<u8>,
+    dropped: bool,
+}
+
+#[derive(Default)]
+pub struct Recorder {
+    /// Held while writing to the file, always taken before `active`
+    file: Mutex<Option<File>>,
+    active: Mutex<Option<Active>>,
+}
+
+impl Recorder {
+    pub fn is_recording(&self) -> bool {
+        self.active.lock().unwrap().is_some()
+    }
+
+    /// Name of the recording being written.
+    pub fn active(&self) -> Option<String> {
+        self.active.lock().unwrap().as_ref().map(|a| a.name.clone())
+    }
+
+    pub fn start(&self) -> Result<RecordingInfo, CommandError> {
+        let mut file = self.file.lock().unwrap();
+        if self.is_recording() {
+            return Err(CommandError::RecordingInProgress);
+        }
+
+        let name = next_name().map_err(storage_error)?;
+        let mut pending = Vec::new();
+        let encoder = RecordingEncoder::new(
+            &RecordingHeader {
+                firmware_version: firmware_version(),
+                sample_rate: SAMPLE_RATE,
+                started_us: micros_since_boot(),
+            },
+            &mut pending,
+        );
+        *file = Some(File::create(path(&name)).map_err(storage_error)?);
+        log::info!("Started recording {}", name);
+
+        *self.active.lock().unwrap() = Some(Active {
+            name: name.clone(),
+            encoder,
+            pending,
+            dropped: false,
+        });
+        Ok(RecordingInfo { name, size: 0 })
+    }
+
+    pub fn stop(&self) -> Result<RecordingInfo, CommandError> {
+        let mut file = self.file.lock().unwrap();
+        let active = self
+            .active
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or(CommandError::NoRecording)?;
+        if let Some(mut file) = file.take() {
+            if let Err(e) = file.write_all(&active.pending) {
+                log::warn!("Error writing recording {}: {:?}", active.name, e);
+            }
+        }
+        log::info!("Stopped recording {}", active.name);
+        Ok(RecordingInfo {
+            size: file_size(&active.name).unwrap_or_default(),
+            name: active.name,
+        })
+    }
+
+    /// Start or stop recording, for the button.
+    pub fn toggle(&self) {
+        let result = match self.is_recording() {
+            true => self.stop(),
+            false => self.start(),
+        };
+        if let Err(e) = result {
+            log::warn!("Error toggling recording: {}", e);
+        }
+    }
+
+    pub fn sample(&self, sample: &Sample) {
+        self.record(|encoder, out| encoder.sample(out, sample));
+    }
+
+    pub fn beat(&self, beat: &BeatEvent) {
+        self.record(|encoder, out| encoder.beat(out, beat));
+    }
+
+    pub fn event(&self, timestamp_us: u64, event: SessionEvent) {
+        self.record(|encoder, out| encoder.event(out, timestamp_us, event));
+    }
+
+    fn record(&self, encode: impl FnOnce(&mut RecordingEncoder, &mut Vec<u8>)) {
+        let mut active = self.active.lock().unwrap();
+        let Some(active) = active.as_mut() else {
+            return;
+        };
+        if active.pending.len() >= MAX_PENDING_LEN {
+            if !active.dropped {
+                log::warn!("Recording {} falling behind, dropping data", active.name);
+                active.dropped = true;
+            }
+            return;
+        }
+        encode(&mut active.encoder, &mut active.pending);
+    }
+
+    /// Append the buffered records to the file, stopping the recording if the
+    /// partition is about to fill up or the write fails.
+    fn flush(&self) {
+        let mut file = self.file.lock().unwrap();
+        let Some(f) = file.as_mut() else {
+            return;
+        };
+        let (name, data) = match self.active.lock().unwrap().as_mut() {
+            Some(active) => {
+                active.dropped = false;
+                (active.name.clone(), mem::take(&mut active.pending))
+            }
+            None => return,
+        };
+
+        let full = usage().is_none_or(|(total, used)| used + data.len() + MIN_FREE_BYTES > total);
+        let result = match full {
+            true => Err(anyhow::anyhow!("partition full")),
+            false => f
+                .write_all(&data)
+                .and_then(|_| f.flush())
+                .map_err(Into::into),
+        };
+        if let Err(e) = result {
+            log::warn!("Stopping recording {}: {:?}", name, e);
+            *file = None;
+            *self.active.lock().unwrap() = None;
+        }
+    }
+
+    pub fn list(&self) -> Result<RecordingList, CommandError> {
+        let mut recordings = Vec::new();
+        for entry in fs::read_dir(MOUNT_POINT).map_err(storage_error)? {
+            let name = entry.map_err(storage_error)?.file_name();
+            let Some(name) = name.to_str().filter(|name| is_recording_name(name)) else {
+                continue;
+            };
+            recordings.push(RecordingInfo {
+                name: name.to_string(),
+                size: file_size(name).map_err(storage_error)?,
+            });
+        }
+        recordings.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let (total, used) = usage().map_err(storage_error)?;
+        Ok(RecordingList {
+            recordings,
+            active: self.active(),
+            used_bytes: used as u32,
+            total_bytes: total as u32,
+        })
+    }
+
+    /// Read a chunk of a recording. The one being written can be read up to its last
+    /// flush.
+    pub fn read(&self, name: &str, offset: u32) -> Result<Reply, CommandError> {
+        let mut file = open(name)?;
+        let total = file.metadata().map_err(storage_error)?.len() as u32;
+        let mut data = vec![0; RECORDING_CHUNK_LEN.min(total.saturating_sub(offset) as usize)];
+        file.seek(SeekFrom::Start(offset as u64))
+            .and_then(|_| file.read_exact(&mut data))
+            .map_err(storage_error)?;
+        Ok(Reply::RecordingChunk {
+            offset,
+            total,
+            data,
+        })
+    }
+
+    pub fn delete(&self, name: &str) -> Result<(), CommandError> {
+        let active = self.active.lock().unwrap().as_ref().map(|a| a.name.clone());
+        if active.as_deref() == Some(name) {
+            return Err(CommandError::RecordingInProgress);
+        }
+        open(name)?;
+        fs::remove_file(path(name)).map_err(storage_error)?;
+        log::info!("Deleted recording {}", name);
+        Ok(())
+    }
+}
+
+pub fn flush_task(recorder: Arc<Recorder>) {
+    loop {
+        thread::sleep(FLUSH_INTERVAL);
+        recorder.flush();
+    }
+}
+
+fn storage_error(e: impl std::fmt::Display) -> CommandError {
+    CommandError::Storage(e.to_string())
+}
+
+fn is_recording_name(name: &str) -> bool {
+    name.strip_prefix(FILE_PREFIX)
+        .and_then(|name| name.strip_suffix(FILE_SUFFIX))
+        .is_some_and(|number| !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()))
+}
+
+fn path(name: &str) -> String {
+    format!("{MOUNT_POINT}/{name}")
+}
+
+fn open(name: &str) -> Result<File, CommandError> {
+    if !is_recording_name(name) {
+        return Err(CommandError::RecordingNotFound(name.to_string()));
+    }
+    File::open(path(name)).map_err(|_| CommandError::RecordingNotFound(name.to_string()))
+}
+
+fn file_size(name: &str) -> std::io::Result<u32> {
+    Ok(fs::metadata(path(name))?.len() as u32)
+}
+
+/// Name following the highest numbered recording on the partition.
+fn next_name() -> std::io::Result<String> {
+    let mut last = 0;
+    for entry in fs::read_dir(MOUNT_POINT)? {
+        let name = entry?.file_name();
+        let number = name
+            .to_str()
+            .filter(|name| is_recording_name(name))
+            .and_then(|name| {
+                name[FILE_PREFIX.len()..name.len() - FILE_SUFFIX.len()]
+                    .parse()
+                    .ok()
+            });
+        last = last.max(number.unwrap_or(0u32));
+    }
+    Ok(format!("{FILE_PREFIX}{:04}{FILE_SUFFIX}", last + 1))
+}
@@ -0,0 +1,150 @@
+//! Post-update self-test.
+//!
+//! With app rollback enabled in the bootloader, a freshly installed image boots in the
+//! pending-verify state. It is only marked valid once the checks in [`SelfTest::run`]
+//! pass, otherwise it is marked invalid and the device reboots into the previous image.
+//! If the image crashes before getting that far, the bootloader rolls back on the next
+//! reset, which [`record_rollback`] notices. The outcome is kept in NVS for the GUI.
+
+use std::net::{Ipv4Addr, SocketAddrV4, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, ensure, Result};
+use esp_idf_svc::ipv4::IpInfo;
+use esp_idf_svc::nvs::*;
+
+use crate::commands::COMMAND_PORT;
+use crate::{ota, VERSION};
+use esp_pulser_protocol::*;
+
+/// How long the checks may keep failing before the image is rolled back. Leaves
+/// time for the DHCP lease and the sensor FIFO to fill.
+const SELF_TEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+const UPDATE_OUTCOME_KEY: &str = "ota_outcome";
+
+pub struct SelfTest {
+    pub status: Arc<Mutex<Status>>,
+    pub health: Arc<Mutex<SensorHealth>>,
+    pub ip_info: Arc<Mutex<Option<IpInfo>>>,
+    pub nvs: Arc<Mutex<EspNvs<NvsDefault>>>,
+}
+
+impl SelfTest {
+    fn run(&self) -> Result<()> {
+        let status = self.status.lock().unwrap().clone();
+        ensure!(status.heart_ok, "heart rate sensor not responding");
+        ensure!(
+            self.health.lock().unwrap().samples_read > 0,
+            "no samples read from the heart rate sensor"
+        );
+        ensure!(status.haptic_ok, "haptic driver not responding");
+        ensure!(
+            self.ip_info.lock().unwrap().is_some(),
+            "no network connection"
+        );
+        TcpStream::connect_timeout(
+            &SocketAddrV4::new(Ipv4Addr::LOCALHOST, COMMAND_PORT).into(),
+            Duration::from_secs(1),
+        )
+        .map_err(|e| anyhow!("command server not accepting connections: {e}"))?;
+        Ok(())
+    }
+}
+
+/// Verify the running image if it was just installed, rolling back on failure.
+pub fn self_test_task(test: SelfTest) {
+    match ota::running_slot_pending_verify() {
+        Ok(true) => (),
+        Ok(false) => return,
+        Err(e) => {
+            log::warn!("Error reading running slot state: {:?}", e);
+            return;
+        }
+    }
+
+    log::info!("Running post-update self-test...");
+    let started = Instant::now();
+    let result = loop {
+        match test.run() {
+            Ok(()) => break Ok(()),
+            Err(e) if started.elapsed() >= SELF_TEST_TIMEOUT => break Err(e),
+            Err(_) => thread::sleep(Duration::from_secs(1)),
+        }
+    };
+
+    match result {
+        Ok(()) => {
+            log::info!("Self-test passed, marking firmware valid");
+            store_outcome(
+                &test.nvs,
+                &UpdateOutcome::Verified {
+                    version: VERSION.to_string(),
+                },
+            );
+            if let Err(e) = ota::mark_running_slot_valid() {
+                log::error!("Error marking firmware valid: {:?}", e);
+            }
+        }
+        Err(e) => {
+            log::error!("Self-test failed: {}, rolling back...", e);
+            store_outcome(
+                &test.nvs,
+                &UpdateOutcome::RolledBack {
+                    version: Some(VERSION.to_string()),
+                    reason: e.to_string(),
+                },
+            );
+            let e = ota::roll_back();
+            log::error!("Error rolling back firmware: {:?}", e);
+        }
+    }
+}
+
+/// Record a rollback done by the bootloader, which happens when an updated image
+/// resets before its self-test has finished.
+pub fn record_rollback(nvs: &Mutex<EspNvs<NvsDefault>>) {
+    let invalid = match ota::last_invalid_slot() {
+        Ok(Some(slot)) => slot,
+        Ok(None) => return,
+        Err(e) => {
+            log::warn!("Error reading invalid slot: {:?}", e);
+            return;
+        }
+    };
+    if let Ok(Some(UpdateOutcome::RolledBack { version, .. })) = update_outcome(nvs) {
+        if version == invalid.version {
+            return;
+        }
+    }
+
+    log::warn!("Rolled back from firmware {:?}", invalid.version);
+    store_outcome(
+        nvs,
+        &UpdateOutcome::RolledBack {
+            version: invalid.version,
+            reason: "firmware reset before completing its self-test".into(),
+        },
+    );
+}
+
+pub fn update_outcome(nvs: &Mutex<EspNvs<NvsDefault>>) -> Result<Option<UpdateOutcome>> {
+    let mut buf = [0; 256];
+    let nvs = nvs.lock().unwrap();
+    match nvs.get_blob(UPDATE_OUTCOME_KEY, &mut buf)? {
+        Some(stored) => Ok(decode(stored).ok()),
+        None => Ok(None),
+    }
+}
+
+fn store_outcome(nvs: &Mutex<EspNvs<NvsDefault>>, outcome: &UpdateOutcome) {
+    let result = nvs
+        .lock()
+        .unwrap()
+        .set_blob(UPDATE_OUTCOME_KEY, &encode(outcome).unwrap());
+    if let Err(e) = result {
+        log::warn!("Error storing update outcome: {:?}", e);
+    }
+}
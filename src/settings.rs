@@ -0,0 +1,121 @@
+//! Persistent user settings.
+//!
+//! All settings live in one versioned blob in the `heart_sensor` namespace, see
+//! [`encode_settings`]. Older firmware kept a few of them under their own keys, which
+//! are migrated into the blob and removed on the first boot after an update.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use esp_idf_svc::nvs::*;
+use esp_pulser_protocol::*;
+
+const SETTINGS_KEY: &str = "settings";
+/// Room for the blob, with plenty to spare for the release URL and future fields
+const SETTINGS_MAX_LEN: usize = 1024;
+
+const LEGACY_LED_AMPLITUDE_KEY: &str = "led_amplitude";
+const LEGACY_HAPTIC_AMPLITUDE_KEY: &str = "haptic_amp";
+
+/// The current settings, written through to NVS on every change.
+pub struct SettingsStore {
+    nvs: Arc<Mutex<EspNvs<NvsDefault>>>,
+    settings: Mutex<Settings>,
+}
+
+impl SettingsStore {
+    pub fn load(nvs: Arc<Mutex<EspNvs<NvsDefault>>>) -> Result<Self> {
+        let settings = load_settings(&mut nvs.lock().unwrap())?;
+        log::info!("Settings: {:?}", settings);
+        Ok(Self {
+            nvs,
+            settings: Mutex::new(settings),
+        })
+    }
+
+    pub fn get(&self) -> Settings {
+        self.settings.lock().unwrap().clone()
+    }
+
+    /// Look at the settings without cloning them, e.g. from the sampling loop.
+    pub fn read<T>(&self, f: impl FnOnce(&Settings) -> T) -> T {
+        f(&self.settings.lock().unwrap())
+    }
+
+    /// Apply `change` and store the result, unless it fails validation. Returns the
+    /// new settings.
+    pub fn update(&self, change: impl FnOnce(&mut Settings)) -> Result<Settings, CommandError> {
+        let mut settings = self.settings.lock().unwrap();
+        let mut updated = settings.clone();
+        change(&mut updated);
+        updated
+            .validate()
+            .map_err(|e| CommandError::InvalidSettings(e.to_string()))?;
+        store_settings(&mut self.nvs.lock().unwrap(), &updated)
+            .map_err(|e| CommandError::Storage(e.to_string()))?;
+        *settings = updated.clone();
+        Ok(updated)
+    }
+}
+
+/// Mirror changed settings into the status, where the sampling loop picks them up.
+pub fn apply_to_status(settings: &Settings, status: &Mutex<Status>) {
+    let mut status = status.lock().unwrap();
+    status.led_amplitude = settings.sensor.led_amplitude;
+    status.haptic_amplitude = settings.haptic.amplitude;
+}
+
+fn load_settings(nvs: &mut EspNvs<NvsDefault>) -> Result<Settings> {
+    let mut buf = [0; SETTINGS_MAX_LEN];
+    match nvs.get_blob(SETTINGS_KEY, &mut buf) {
+        Ok(Some(bytes)) => match decode_settings(bytes) {
+            Ok(settings) => Ok(settings),
+            // Written by newer firmware. The blob is left alone until a setting is
+            // changed here, which stores the defaults with that change over it.
+            Err(e @ SettingsError::UnknownVersion(_)) => {
+                log::warn!(
+                    "Ignoring stored settings, using defaults: {}. They are replaced on the next settings change",
+                    e
+                );
+                Ok(Settings::default())
+            }
+            Err(e) => reset_settings(nvs, e.to_string()),
+        },
+        Ok(None) => migrate_legacy(nvs),
+        Err(e) => reset_settings(nvs, e.to_string()),
+    }
+}
+
+fn reset_settings(nvs: &mut EspNvs<NvsDefault>, reason: String) -> Result<Settings> {
+    log::warn!(
+        "Stored settings unusable, resetting to defaults: {}",
+        reason
+    );
+    let settings = Settings::default();
+    store_settings(nvs, &settings)?;
+    Ok(settings)
+}
+
+fn migrate_legacy(nvs: &mut EspNvs<NvsDefault>) -> Result<Settings> {
+    let legacy = LegacySettings {
+        led_amplitude: nvs.get_u8(LEGACY_LED_AMPLITUDE_KEY)?,
+        haptic_amplitude: nvs.get_u8(LEGACY_HAPTIC_AMPLITUDE_KEY)?,
+    };
+    log::info!("Migrating settings from {:?}", legacy);
+
+    let mut settings = Settings::from_legacy(legacy);
+    if let Err(e) = settings.validate() {
+        log::warn!("Migrated settings invalid, using defaults: {}", e);
+        settings = Settings::default();
+    }
+    store_settings(nvs, &settings)?;
+    for key in [LEGACY_LED_AMPLITUDE_KEY, LEGACY_HAPTIC_AMPLITUDE_KEY] {
+        nvs.remove(key)?;
+    }
+    Ok(settings)
+}
+
+fn store_settings(nvs: &mut EspNvs<NvsDefault>, settings: &Settings) -> Result<()> {
+    nvs.set_blob(SETTINGS_KEY, &encode_settings(settings)?)?;
+    Ok(())
+}
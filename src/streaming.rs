@@ -0,0 +1,254 @@
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use esp_pulser_protocol::*;
+
+use crate::pulse_sensor::SAMPLE_RATE;
+
+/// Sample packet rate used for legacy subscribers, matching the old fixed decimation.
+const LEGACY_SAMPLE_RATE_HZ: u16 = 25;
+/// History entries sent at once during a replay, between which live data is recorded
+const REPLAY_CHUNK_LEN: usize = 32;
+/// Pause between replay chunks, so the datagrams are not dropped by the network stack
+const REPLAY_CHUNK_DELAY: Duration = Duration::from_millis(5);
+
+struct Subscriber {
+    addr: SocketAddr,
+    streams: Streams,
+    sample_rate_hz: u16,
+    sample_divider: u64,
+    legacy: bool,
+    last_seen: Instant,
+    /// Live packets are held back while the history is replayed
+    replaying: bool,
+}
+
+impl Subscriber {
+    fn new(addr: SocketAddr, streams: Streams, sample_rate_hz: u16, legacy: bool) -> Self {
+        let sample_rate_hz = sample_rate_hz.clamp(1, SAMPLE_RATE as u16);
+        Self {
+            addr,
+            streams,
+            sample_rate_hz,
+            sample_divider: (SAMPLE_RATE as u64 / sample_rate_hz as u64).max(1),
+            legacy,
+            last_seen: Instant::now(),
+            replaying: false,
+        }
+    }
+
+    fn wants(&self, packet: &Packet) -> bool {
+        !self.replaying && self.filter(packet, self.sample_divider)
+    }
+
+    /// Only every `history_divider`-th sample is kept in the history, so the replayed
+    /// samples are decimated to a multiple of that.
+    fn wants_replayed(&self, packet: &Packet, history_divider: u64) -> bool {
+        let divider = self.sample_divider.div_ceil(history_divider) * history_divider;
+        self.filter(packet, divider)
+    }
+
+    fn filter(&self, packet: &Packet, sample_divider: u64) -> bool {
+        let sampled = |sample: &Sample| sample.index % sample_divider == 0;
+        match packet {
+            Packet::Status(_) => self.streams.status,
+            Packet::RawHeartRate(sample) => self.streams.raw && sampled(sample),
+            Packet::HeartRate(sample) => self.streams.filtered && sampled(sample),
+            Packet::Bpm(_) => self.streams.bpm,
+            Packet::Beat(_) => self.streams.beats,
+            Packet::Update(_) | Packet::Event(_) => self.streams.status,
+        }
+    }
+
+    fn info(&self) -> SubscriberInfo {
+        SubscriberInfo {
+            addr: self.addr,
+            streams: self.streams,
+            sample_rate_hz: self.sample_rate_hz,
+            legacy: self.legacy,
+        }
+    }
+}
+
+/// Registry of UDP destinations the data stream is sent to.
+pub struct Subscribers {
+    socket: UdpSocket,
+    subscribers: Vec<Subscriber>,
+    /// The last seconds of the stream, for [`replay_history`]
+    history: History,
+}
+
+impl Subscribers {
+    pub fn new(socket: UdpSocket) -> Self {
+        Self {
+            socket,
+            subscribers: Vec::new(),
+            history: History::new(SAMPLE_RATE),
+        }
+    }
+
+    pub fn subscribe(
+        &mut self,
+        addr: SocketAddr,
+        subscription: &Subscription,
+    ) -> Result<(), CommandError> {
+        self.add(Subscriber::new(
+            addr,
+            subscription.streams,
+            subscription.sample_rate_hz,
+            false,
+        ))
+    }
+
+    /// Legacy clients get every stream at the old fixed rate. They never send
+    /// keepalives, so they stay subscribed until they unsubscribe.
+    pub fn subscribe_legacy(&mut self, addr: SocketAddr) -> Result<(), CommandError> {
+        self.add(Subscriber::new(
+            addr,
+            Streams::ALL,
+            LEGACY_SAMPLE_RATE_HZ,
+            true,
+        ))
+    }
+
+    /// Replace the subscriber with the same address, or add it if there is room.
+    fn add(&mut self, subscriber: Subscriber) -> Result<(), CommandError> {
+        let addr = subscriber.addr;
+        if let Some(existing) = self.subscribers.iter_mut().find(|s| s.addr == addr) {
+            *existing = subscriber;
+            return Ok(());
+        }
+        if self.subscribers.len() >= MAX_SUBSCRIBERS {
+            return Err(CommandError::TooManySubscribers);
+        }
+        log::info!("New stream subscriber: {}", addr);
+        self.subscribers.push(subscriber);
+        Ok(())
+    }
+
+    pub fn unsubscribe(&mut self, addr: SocketAddr) -> Result<(), CommandError> {
+        let count = self.subscribers.len();
+        self.subscribers.retain(|s| s.addr != addr);
+        if self.subscribers.len() == count {
+            return Err(CommandError::NotSubscribed);
+        }
+        log::info!("Stream subscriber left: {}", addr);
+        Ok(())
+    }
+
+    pub fn keepalive(&mut self, addr: SocketAddr) {
+        if let Some(subscriber) = self.subscribers.iter_mut().find(|s| s.addr == addr) {
+            subscriber.last_seen = Instant::now();
+        }
+    }
+
+    pub fn expire(&mut self) {
+        let timeout = Duration::from_secs(SUBSCRIBER_TIMEOUT_SECS);
+        self.subscribers.retain(|s| {
+            let alive = s.legacy || s.last_seen.elapsed() < timeout;
+            if !alive {
+                log::info!("Stream subscriber timed out: {}", s.addr);
+            }
+            alive
+        });
+    }
+
+    pub fn list(&self) -> Vec<SubscriberInfo> {
+        self.subscribers.iter().map(Subscriber::info).collect()
+    }
+
+    pub fn legacy_target(&self) -> Option<SocketAddr> {
+        self.subscribers.iter().find(|s| s.legacy).map(|s| s.addr)
+    }
+
+    pub fn send(&self, packet: &Packet) {
+        let mut encoded = None;
+        for subscriber in self.subscribers.iter().filter(|s| s.wants(packet)) {
+            let data = encoded.get_or_insert_with(|| encode(packet).unwrap());
+            if let Err(e) = self.socket.send_to(data, subscriber.addr) {
+                log::warn!("Error sending data: {:?}", e);
+            }
+        }
+    }
+
+    /// Keep a packet in the history.
+    pub fn record(&mut self, packet: &Packet) {
+        self.history.record(packet);
+    }
+
+    fn subscriber_mut(&mut self, addr: SocketAddr) -> Result<&mut Subscriber, CommandError> {
+        self.subscribers
+            .iter_mut()
+            .find(|s| s.addr == addr)
+            .ok_or(CommandError::NotSubscribed)
+    }
+
+    /// Send the history entries from `from` on to a replaying subscriber. Returns where
+    /// to continue, or `None` once it has caught up and receives the live stream again.
+    fn replay_chunk(&mut self, addr: SocketAddr, from: u64) -> Result<Option<u64>, CommandError> {
+        let (packets, next) = self.history.read(from, REPLAY_CHUNK_LEN);
+        let history_divider = self.history.sample_divider();
+        let caught_up = next == self.history.end();
+        let subscriber = self.subscriber_mut(addr)?;
+        let packets: Vec<_> = packets
+            .iter()
+            .filter(|packet| subscriber.wants_replayed(packet, history_divider))
+            .collect();
+        // Nothing is recorded while the lock is held, so no live packet falls in between
+        if caught_up {
+            subscriber.replaying = false;
+        }
+        for packet in packets {
+            if let Err(e) = self.socket.send_to(&encode(packet).unwrap(), addr) {
+                log::warn!("Error replaying history: {:?}", e);
+            }
+        }
+        Ok((!caught_up).then_some(next))
+    }
+}
+
+/// Send the last seconds of the stream to a subscriber, in the order and with the
+/// timestamps they were originally sent with. Its live stream pauses meanwhile and
+/// continues right after the last replayed packet.
+pub fn replay_history(
+    subscribers: &Mutex<Subscribers>,
+    addr: SocketAddr,
+) -> Result<(), CommandError> {
+    let mut next = {
+        let mut subscribers = subscribers.lock().unwrap();
+        let start = subscribers.history.start();
+        let subscriber = subscribers.subscriber_mut(addr)?;
+        if subscriber.replaying {
+            return Err(CommandError::ReplayInProgress);
+        }
+        subscriber.replaying = true;
+        start
+    };
+    log::info!("Replaying history to {}", addr);
+
+    loop {
+        match subscribers.lock().unwrap().replay_chunk(addr, next)? {
+            Some(from) => next = from,
+            None => return Ok(()),
+        }
+        thread::sleep(REPLAY_CHUNK_DELAY);
+    }
+}
+
+/// Refreshes subscribers on every datagram they send to the data port and drops the
+/// ones that went quiet.
+pub fn keepalive_task(subscribers: Arc<Mutex<Subscribers>>, socket: UdpSocket) {
+    let mut buf = [0; 64];
+    socket
+        .set_read_timeout(Some(Duration::from_secs(1)))
+        .unwrap();
+
+    loop {
+        if let Ok((_, addr)) = socket.recv_from(&mut buf) {
+            subscribers.lock().unwrap().keepalive(addr);
+        }
+        subscribers.lock().unwrap().expire();
+    }
+}
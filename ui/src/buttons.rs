@@ -0,0 +1,251 @@
+//! Debounced button input, turned into gestures.
+//!
+//! [`ButtonInput`] polls the pins of the board buttons and sends a [`ButtonEvent`] for
+//! every click, double click, long press and hold on a channel. It is independent of
+//! the GPIO driver: the device reads its pins through [`ButtonPin`] closures, while the
+//! tests script the pin levels and the time of every poll.
+
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often [`ButtonInput::run`] reads the pins
+pub const BUTTON_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Timing of the gestures.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GestureConfig {
+    /// How long a new level has to be read in a row before it counts
+    pub debounce: Duration,
+    /// Longest gap between two clicks that still makes a double click
+    pub double_click: Duration,
+    /// How long a button has to be held for a long press
+    pub long_press: Duration,
+    /// Interval of the hold events following a long press
+    pub hold_interval: Duration,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        Self {
+            debounce: Duration::from_millis(20),
+            double_click: Duration::from_millis(300),
+            long_press: Duration::from_millis(800),
+            hold_interval: Duration::from_millis(200),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    D0,
+    D1,
+    D2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gesture {
+    /// Pressed and released, with no second press following right after
+    Click,
+    /// Two clicks in quick succession
+    DoubleClick,
+    /// Held down for [`GestureConfig::long_press`], sent while still held
+    LongPress,
+    /// Still held after a long press, repeated with the time since the press
+    Hold(Duration),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ButtonEvent {
+    pub button: Button,
+    pub gesture: Gesture,
+}
+
+/// A button input, read as pressed or not regardless of its polarity.
+pub trait ButtonPin {
+    fn is_pressed(&mut self) -> bool;
+}
+
+impl<F: FnMut() -> bool> ButtonPin for F {
+    fn is_pressed(&mut self) -> bool {
+        self()
+    }
+}
+
+/// Gesture recognition for a single button, fed with the pin level on every poll.
+///
+/// The first level read is taken as is, so a button held down from the start is
+/// pressed right away and goes on to a long press.
+#[derive(Debug, Clone)]
+pub struct GestureDetector {
+    config: GestureConfig,
+    /// Debounced level, `None` before the first poll
+    pressed: Option<bool>,
+    /// When the pin started reading the other level
+    bouncing: Option<Instant>,
+    pressed_at: Option<Instant>,
+    released_at: Option<Instant>,
+    /// The current press was a long press
+    long: bool,
+    next_hold: Option<Instant>,
+    /// A click waiting for the double click window to pass
+    click_pending: bool,
+}
+
+impl GestureDetector {
+    pub fn new(config: GestureConfig) -> Self {
+        Self {
+            config,
+            pressed: None,
+            bouncing: None,
+            pressed_at: None,
+            released_at: None,
+            long: false,
+            next_hold: None,
+            click_pending: false,
+        }
+    }
+
+    /// Debounced state of the button.
+    pub fn is_pressed(&self) -> bool {
+        self.pressed == Some(true)
+    }
+
+    pub fn update(&mut self, pressed: bool, now: Instant) -> Option<Gesture> {
+        let Some(stable) = self.pressed else {
+            self.pressed = Some(pressed);
+            return match pressed {
+                true => self.press(now),
+                false => None,
+            };
+        };
+
+        if pressed != stable {
+            let since = *self.bouncing.get_or_insert(now);
+            if now.duration_since(since) >= self.config.debounce {
+                self.bouncing = None;
+                self.pressed = Some(pressed);
+                return match pressed {
+                    true => self.press(since),
+                    false => self.release(since),
+                };
+            }
+        } else {
+            self.bouncing = None;
+        }
+
+        match stable {
+            true => self.held(now),
+            false => self.idle(now),
+        }
+    }
+
+    fn press(&mut self, at: Instant) -> Option<Gesture> {
+        self.pressed_at = Some(at);
+        self.long = false;
+        // A click whose window passed between two polls
+        self.idle(at)
+    }
+
+    fn release(&mut self, at: Instant) -> Option<Gesture> {
+        self.released_at = Some(at);
+        if self.long {
+            return None;
+        }
+        match self.click_pending {
+            true => {
+                self.click_pending = false;
+                Some(Gesture::DoubleClick)
+            }
+            false => {
+                self.click_pending = true;
+                None
+            }
+        }
+    }
+
+    fn held(&mut self, now: Instant) -> Option<Gesture> {
+        let pressed_at = self.pressed_at?;
+        let held = now.duration_since(pressed_at);
+        if !self.long && held >= self.config.long_press {
+            // The click before a long press is not lost
+            if self.click_pending {
+                self.click_pending = false;
+                return Some(Gesture::Click);
+            }
+            self.long = true;
+            self.next_hold = Some(pressed_at + self.config.long_press + self.config.hold_interval);
+            return Some(Gesture::LongPress);
+        }
+        match self.next_hold {
+            Some(next) if self.long && now >= next => {
+                self.next_hold = Some(next + self.config.hold_interval);
+                Some(Gesture::Hold(held))
+            }
+            _ => None,
+        }
+    }
+
+    fn idle(&mut self, now: Instant) -> Option<Gesture> {
+        let released_at = self.released_at?;
+        if self.click_pending && now.duration_since(released_at) >= self.config.double_click {
+            self.click_pending = false;
+            return Some(Gesture::Click);
+        }
+        None
+    }
+}
+
+/// The board buttons, polled together.
+pub struct ButtonInput {
+    buttons: Vec<(Button, Box<dyn ButtonPin + Send>, GestureDetector)>,
+    config: GestureConfig,
+    events: Sender<ButtonEvent>,
+}
+
+impl ButtonInput {
+    pub fn new(config: GestureConfig, events: Sender<ButtonEvent>) -> Self {
+        Self {
+            buttons: Vec::new(),
+            config,
+            events,
+        }
+    }
+
+    pub fn with(mut self, button: Button, pin: impl ButtonPin + Send + 'static) -> Self {
+        let detector = GestureDetector::new(self.config.clone());
+        self.buttons.push((button, Box::new(pin), detector));
+        self
+    }
+
+    /// Debounced state of a button, as of the last poll.
+    pub fn is_pressed(&self, button: Button) -> bool {
+        self.buttons
+            .iter()
+            .any(|(b, _, detector)| *b == button && detector.is_pressed())
+    }
+
+    /// Read all pins and send the gestures they complete. Returns `false` once the
+    /// receiving end of the channel is gone.
+    pub fn poll(&mut self, now: Instant) -> bool {
+        for (button, pin, detector) in &mut self.buttons {
+            if let Some(gesture) = detector.update(pin.is_pressed(), now) {
+                let event = ButtonEvent {
+                    button: *button,
+                    gesture,
+                };
+                if self.events.send(event).is_err() {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Poll every [`BUTTON_POLL_INTERVAL`] until nobody listens anymore.
+    pub fn run(mut self) {
+        while self.poll(Instant::now()) {
+            thread::sleep(BUTTON_POLL_INTERVAL);
+        }
+    }
+}
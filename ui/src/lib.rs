@@ -0,0 +1,22 @@
+//! Screens of the esp-pulser display, and the button gestures that drive them.
+//!
+//! Everything is drawn to any [`DrawTarget`] with [`Rgb565`] colors, the ST7789 on the
+//! device and an in-memory framebuffer in the host tests, which compare every screen
+//! against the snapshots in `tests/snapshots`.
+//!
+//! [`DrawTarget`]: embedded_graphics::draw_target::DrawTarget
+//! [`Rgb565`]: embedded_graphics::pixelcolor::Rgb565
+
+mod buttons;
+mod live;
+mod menu;
+mod message;
+
+pub use buttons::*;
+pub use live::*;
+pub use menu::*;
+pub use message::*;
+
+/// Size of the display in landscape orientation
+pub const WIDTH: u32 = 240;
+pub const HEIGHT: u32 = 135;
@@ -0,0 +1,285 @@
+//! Live screen on the TFT: a sweeping PPG waveform, the current BPM, a beat indicator,
+//! finger contact and the network state.
+//!
+//! The display shares its SPI bus with the W5500, so the screen is never redrawn as a
+//! whole after the first frame. Every element remembers what it last drew and is only
+//! redrawn when that changes. The waveform is drawn like on a patient monitor: a cursor
+//! sweeps left to right, each new point clears a narrow band ahead of it and draws a
+//! single line segment, which keeps the redrawn area to a few columns per frame.
+
+use std::collections::VecDeque;
+use std::net::Ipv4Addr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use embedded_graphics::{
+    mono_font::{ascii, MonoTextStyle},
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{Circle, Line, PrimitiveStyle, Rectangle},
+    text::{Baseline, Text},
+};
+
+use crate::{HEIGHT, WIDTH};
+
+/// Height of the bar with the BPM and state above the waveform
+const HEADER_HEIGHT: u32 = 32;
+const WAVEFORM_TOP: i32 = HEADER_HEIGHT as i32 + 2;
+const WAVEFORM_HEIGHT: u32 = HEIGHT - WAVEFORM_TOP as u32;
+/// Waveform points per second, so a sweep across the screen takes four seconds
+const WAVEFORM_RATE_HZ: u32 = 60;
+/// Columns cleared ahead of the sweep cursor
+const SWEEP_GAP: u32 = 6;
+/// Points kept while the display thread is not drawing
+const MAX_PENDING_POINTS: usize = WIDTH as usize;
+/// How long the beat indicator stays lit
+const BEAT_FLASH: Duration = Duration::from_millis(200);
+
+const BACKGROUND: Rgb565 = Rgb565::BLACK;
+const WAVEFORM_COLOR: Rgb565 = Rgb565::GREEN;
+
+const BPM_AREA: Rectangle = Rectangle::new(Point::new(4, 6), Size::new(100, 20));
+const BEAT_AREA: Rectangle = Rectangle::new(Point::new(108, 8), Size::new(16, 16));
+const FINGER_AREA: Rectangle = Rectangle::new(Point::new(136, 4), Size::new(100, 10));
+const NETWORK_AREA: Rectangle = Rectangle::new(Point::new(136, 18), Size::new(100, 10));
+
+/// What the sampling loop hands over to the display thread.
+#[derive(Debug, Default)]
+struct FeedState {
+    /// Decimated filtered samples not drawn yet
+    points: VecDeque<f32>,
+    sum: f32,
+    summed: u32,
+    bpm: f32,
+    finger: bool,
+    last_beat: Option<Instant>,
+}
+
+#[derive(Debug)]
+pub struct Feed {
+    /// Sensor samples averaged into one waveform point
+    decimation: u32,
+    state: Mutex<FeedState>,
+}
+
+impl Feed {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            decimation: (sample_rate as u32 / WAVEFORM_RATE_HZ).max(1),
+            state: Mutex::default(),
+        }
+    }
+
+    /// Called for every sensor sample. Samples are averaged down to the waveform rate,
+    /// leaving out non-finite ones.
+    pub fn sample(&self, filtered: f32, bpm: f32, finger: bool, beat: bool) {
+        let mut state = self.state.lock().unwrap();
+        if filtered.is_finite() {
+            state.sum += filtered;
+            state.summed += 1;
+        }
+        if state.summed >= self.decimation {
+            let point = state.sum / state.summed as f32;
+            (state.sum, state.summed) = (0.0, 0);
+            if state.points.len() >= MAX_PENDING_POINTS {
+                state.points.pop_front();
+            }
+            state.points.push_back(point);
+        }
+        state.bpm = bpm;
+        state.finger = finger;
+        if beat {
+            state.last_beat = Some(Instant::now());
+        }
+    }
+
+    /// Take everything needed for the next frame.
+    pub fn frame(&self, ip: Option<Ipv4Addr>) -> Frame {
+        let mut state = self.state.lock().unwrap();
+        Frame {
+            points: state.points.drain(..).collect(),
+            bpm: state.bpm,
+            beat: state
+                .last_beat
+                .is_some_and(|beat| beat.elapsed() < BEAT_FLASH),
+            finger: state.finger,
+            ip,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Frame {
+    /// New waveform points since the last frame
+    pub points: Vec<f32>,
+    pub bpm: f32,
+    /// A beat was detected a moment ago
+    pub beat: bool,
+    pub finger: bool,
+    pub ip: Option<Ipv4Addr>,
+}
+
+/// Vertical scaling of the waveform, fitted to the last sweep whenever the cursor
+/// wraps around.
+#[derive(Debug, Clone, Copy)]
+struct Scale {
+    min: f32,
+    max: f32,
+}
+
+impl Scale {
+    fn fit(values: impl Iterator<Item = f32>) -> Option<Self> {
+        let (min, max) = values.fold((f32::MAX, f32::MIN), |(min, max), value| {
+            (min.min(value), max.max(value))
+        });
+        // Keep some headroom, and a minimum range so noise is not blown up
+        let margin = ((max - min) * 0.1).max(50.0);
+        (min <= max).then_some(Self {
+            min: min - margin,
+            max: max + margin,
+        })
+    }
+
+    fn y(&self, value: f32) -> i32 {
+        let relative = ((value - self.min) / (self.max - self.min)).clamp(0.0, 1.0);
+        WAVEFORM_TOP + ((1.0 - relative) * (WAVEFORM_HEIGHT - 1) as f32) as i32
+    }
+}
+
+/// What is on the screen, so only changes are drawn.
+#[derive(Debug, Default)]
+pub struct LiveScreen {
+    /// Drawn since the display was last cleared
+    drawn: bool,
+    bpm: Option<u32>,
+    beat: Option<bool>,
+    finger: Option<bool>,
+    ip: Option<Option<Ipv4Addr>>,
+    /// Column the next waveform point is drawn at
+    cursor: u32,
+    last_y: Option<i32>,
+    scale: Option<Scale>,
+    /// Points of the current sweep, for fitting the scale of the next one
+    sweep: Vec<f32>,
+}
+
+impl LiveScreen {
+    /// Redraw everything on the next frame, after something else drew on the display.
+    pub fn invalidate(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn draw<D: DrawTarget<Color = Rgb565>>(
+        &mut self,
+        display: &mut D,
+        frame: &Frame,
+    ) -> Result<(), D::Error> {
+        if !self.drawn {
+            display.clear(BACKGROUND)?;
+            Line::new(
+                Point::new(0, HEADER_HEIGHT as i32),
+                Point::new(WIDTH as i32 - 1, HEADER_HEIGHT as i32),
+            )
+            .into_styled(PrimitiveStyle::with_stroke(Rgb565::CSS_DIM_GRAY, 1))
+            .draw(display)?;
+            self.drawn = true;
+        }
+
+        let bpm = frame.bpm.round() as u32;
+        if self.bpm != Some(bpm) {
+            self.bpm = Some(bpm);
+            let text = match bpm {
+                0 => "-- BPM".to_string(),
+                bpm => format!("{bpm} BPM"),
+            };
+            draw_text(display, BPM_AREA, &text, &ascii::FONT_10X20, Rgb565::WHITE)?;
+        }
+
+        if self.beat != Some(frame.beat) {
+            self.beat = Some(frame.beat);
+            let color = match frame.beat {
+                true => Rgb565::RED,
+                false => Rgb565::CSS_DARK_SLATE_GRAY,
+            };
+            Circle::new(BEAT_AREA.top_left, BEAT_AREA.size.width)
+                .into_styled(PrimitiveStyle::with_fill(color))
+                .draw(display)?;
+        }
+
+        if self.finger != Some(frame.finger) {
+            self.finger = Some(frame.finger);
+            let (text, color) = match frame.finger {
+                true => ("Finger on", Rgb565::GREEN),
+                false => ("No finger", Rgb565::YELLOW),
+            };
+            draw_text(display, FINGER_AREA, text, &ascii::FONT_6X10, color)?;
+        }
+
+        if self.ip != Some(frame.ip) {
+            self.ip = Some(frame.ip);
+            let (text, color) = match frame.ip {
+                Some(ip) => (ip.to_string(), Rgb565::WHITE),
+                None => ("Unconnected".to_string(), Rgb565::RED),
+            };
+            draw_text(display, NETWORK_AREA, &text, &ascii::FONT_6X10, color)?;
+        }
+
+        // A single NaN or infinity would wreck the scale of the whole sweep
+        for &point in frame.points.iter().filter(|point| point.is_finite()) {
+            self.draw_point(display, point)?;
+        }
+        Ok(())
+    }
+
+    fn draw_point<D: DrawTarget<Color = Rgb565>>(
+        &mut self,
+        display: &mut D,
+        value: f32,
+    ) -> Result<(), D::Error> {
+        if self.cursor == 0 {
+            if let Some(scale) = Scale::fit(self.sweep.drain(..)) {
+                self.scale = Some(scale);
+            }
+            self.last_y = None;
+        }
+        let Some(scale) = self.scale.or_else(|| Scale::fit([value].into_iter())) else {
+            return Ok(());
+        };
+        self.scale = Some(scale);
+        self.sweep.push(value);
+
+        let x = self.cursor as i32;
+        let gap = SWEEP_GAP.min(WIDTH - self.cursor);
+        Rectangle::new(Point::new(x, WAVEFORM_TOP), Size::new(gap, WAVEFORM_HEIGHT))
+            .into_styled(PrimitiveStyle::with_fill(BACKGROUND))
+            .draw(display)?;
+
+        let y = scale.y(value);
+        let from = Point::new((x - 1).max(0), self.last_y.unwrap_or(y));
+        Line::new(from, Point::new(x, y))
+            .into_styled(PrimitiveStyle::with_stroke(WAVEFORM_COLOR, 1))
+            .draw(display)?;
+
+        self.last_y = Some(y);
+        self.cursor = (self.cursor + 1) % WIDTH;
+        Ok(())
+    }
+}
+
+fn draw_text<D: DrawTarget<Color = Rgb565>>(
+    display: &mut D,
+    area: Rectangle,
+    text: &str,
+    font: &embedded_graphics::mono_font::MonoFont,
+    color: Rgb565,
+) -> Result<(), D::Error> {
+    display.fill_solid(&area, BACKGROUND)?;
+    Text::with_baseline(
+        text,
+        area.top_left,
+        MonoTextStyle::new(font, color),
+        Baseline::Top,
+    )
+    .draw(display)?;
+    Ok(())
+}
@@ -0,0 +1,390 @@
+//! On-device menu, navigated with the three buttons.
+//!
+//! D0 opens the menu and enters the selected item, D1 and D2 move up and down. Value
+//! items open an editor in which D1 and D2 decrease and increase the value and D0
+//! stores it. Status pages go back to the list on D0. Outside the menu, D2 starts or
+//! stops a recording. What a press leads to is returned as an [`Action`] for the
+//! firmware to carry out.
+
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use embedded_graphics::{
+    mono_font::{ascii, MonoTextStyle},
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::{Baseline, Text},
+};
+
+use esp_pulser_protocol::*;
+
+use crate::{Button, WIDTH};
+
+/// Status pages are redrawn this often while shown
+const PAGE_REFRESH: Duration = Duration::from_secs(1);
+
+/// Backlight timeouts offered in the menu, in seconds
+const BACKLIGHT_CHOICES: [u8; 5] = [0, 5, 10, 30, 60];
+
+const ROW_HEIGHT: i32 = 22;
+/// Rows of the item list that fit on the screen
+const VISIBLE_ROWS: usize = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Item {
+    Status,
+    Network,
+    LedAmplitude,
+    HapticAmplitude,
+    HapticEffect,
+    HapticOnBeats,
+    Display,
+    Backlight,
+    Close,
+}
+
+const ITEMS: [Item; 9] = [
+    Item::Status,
+    Item::Network,
+    Item::LedAmplitude,
+    Item::HapticAmplitude,
+    Item::HapticEffect,
+    Item::HapticOnBeats,
+    Item::Display,
+    Item::Backlight,
+    Item::Close,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Value {
+    Amplitude(u8),
+    Effect(HapticEffect),
+    Toggle(bool),
+    Seconds(u8),
+}
+
+impl Item {
+    fn label(self) -> &'static str {
+        match self {
+            Item::Status => "Status",
+            Item::Network => "Network",
+            Item::LedAmplitude => "LED amp",
+            Item::HapticAmplitude => "Haptic amp",
+            Item::HapticEffect => "Effect",
+            Item::HapticOnBeats => "Haptic",
+            Item::Display => "Display",
+            Item::Backlight => "Backlight",
+            Item::Close => "Close",
+        }
+    }
+
+    /// Current value of an item that can be edited.
+    fn value(self, settings: &Settings) -> Option<Value> {
+        Some(match self {
+            Item::LedAmplitude => Value::Amplitude(settings.sensor.led_amplitude),
+            Item::HapticAmplitude => Value::Amplitude(settings.haptic.amplitude),
+            Item::HapticEffect => Value::Effect(settings.haptic.effect),
+            Item::HapticOnBeats => Value::Toggle(settings.haptic.enabled),
+            Item::Display => Value::Toggle(settings.display.enabled),
+            Item::Backlight => Value::Seconds(settings.display.backlight_secs),
+            Item::Status | Item::Network | Item::Close => return None,
+        })
+    }
+
+    fn set(self, settings: &mut Settings, value: Value) {
+        match (self, value) {
+            (Item::LedAmplitude, Value::Amplitude(value)) => settings.sensor.led_amplitude = value,
+            (Item::HapticAmplitude, Value::Amplitude(value)) => settings.haptic.amplitude = value,
+            (Item::HapticEffect, Value::Effect(effect)) => settings.haptic.effect = effect,
+            (Item::HapticOnBeats, Value::Toggle(enabled)) => settings.haptic.enabled = enabled,
+            (Item::Display, Value::Toggle(enabled)) => settings.display.enabled = enabled,
+            (Item::Backlight, Value::Seconds(secs)) => settings.display.backlight_secs = secs,
+            _ => unreachable!("{:?} cannot be set to {:?}", self, value),
+        }
+    }
+
+    fn step(self, value: Value, up: bool) -> Value {
+        match value {
+            Value::Amplitude(value) => {
+                let step = match self {
+                    Item::LedAmplitude => 5,
+                    _ => 15,
+                };
+                Value::Amplitude(match up {
+                    true => value.saturating_add(step),
+                    false => value.saturating_sub(step),
+                })
+            }
+            Value::Effect(effect) => {
+                let all = HapticEffect::ALL;
+                let index = all.iter().position(|e| *e == effect).unwrap_or(0);
+                let next = match up {
+                    true => (index + 1) % all.len(),
+                    false => (index + all.len() - 1) % all.len(),
+                };
+                Value::Effect(all[next])
+            }
+            Value::Toggle(enabled) => Value::Toggle(!enabled),
+            Value::Seconds(secs) => Value::Seconds(
+                match up {
+                    true => BACKLIGHT_CHOICES.into_iter().find(|c| *c > secs),
+                    false => BACKLIGHT_CHOICES.into_iter().rev().find(|c| *c < secs),
+                }
+                .unwrap_or(secs),
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Amplitude(value) => write!(f, "{value}"),
+            Value::Effect(effect) => write!(f, "{effect:?}"),
+            Value::Toggle(true) => write!(f, "On"),
+            Value::Toggle(false) => write!(f, "Off"),
+            Value::Seconds(0) => write!(f, "Always"),
+            Value::Seconds(secs) => write!(f, "{secs} s"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum View {
+    Closed,
+    List,
+    Page(Item),
+    Edit(Item, Value),
+}
+
+/// A setting changed in the menu, to be stored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Change {
+    item: Item,
+    value: Value,
+}
+
+impl Change {
+    pub fn apply(&self, settings: &mut Settings) {
+        self.item.set(settings, self.value);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Store(Change),
+    ToggleRecording,
+}
+
+/// Everything the status pages show.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageInfo {
+    pub status: Status,
+    pub ip: Option<Ipv4Addr>,
+    pub mac: [u8; 6],
+    pub subscribers: usize,
+    /// Name of the recording being written
+    pub recording: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Menu {
+    view: View,
+    selected: usize,
+    /// The view changed since it was last drawn
+    dirty: bool,
+    drawn_at: Option<Instant>,
+}
+
+impl Default for Menu {
+    fn default() -> Self {
+        Self {
+            view: View::Closed,
+            selected: 0,
+            dirty: false,
+            drawn_at: None,
+        }
+    }
+}
+
+impl Menu {
+    pub fn is_open(&self) -> bool {
+        self.view != View::Closed
+    }
+
+    pub fn close(&mut self) {
+        if self.is_open() {
+            self.view = View::Closed;
+            self.dirty = true;
+        }
+    }
+
+    pub fn press(&mut self, button: Button, settings: &Settings) -> Option<Action> {
+        let items = ITEMS.len();
+        let mut action = None;
+        self.view = match (self.view, button) {
+            (View::Closed, Button::D0) => {
+                self.selected = 0;
+                View::List
+            }
+            (View::Closed, Button::D2) => return Some(Action::ToggleRecording),
+            (View::Closed, Button::D1) => return None,
+
+            (View::List, Button::D1) => {
+                self.selected = (self.selected + items - 1) % items;
+                View::List
+            }
+            (View::List, Button::D2) => {
+                self.selected = (self.selected + 1) % items;
+                View::List
+            }
+            (View::List, Button::D0) => {
+                let item = ITEMS[self.selected];
+                match (item, item.value(settings)) {
+                    (Item::Close, _) => View::Closed,
+                    (_, Some(value)) => View::Edit(item, value),
+                    (_, None) => View::Page(item),
+                }
+            }
+
+            (View::Page(_), Button::D0) => View::List,
+            (View::Page(_), _) => return None,
+
+            (View::Edit(item, value), Button::D1 | Button::D2) => {
+                View::Edit(item, item.step(value, button == Button::D2))
+            }
+            (View::Edit(item, value), Button::D0) => {
+                action = Some(Action::Store(Change { item, value }));
+                View::List
+            }
+        };
+        self.dirty = true;
+        action
+    }
+
+    /// Whether the menu has to be drawn, either because it changed or because a status
+    /// page is due for a refresh.
+    pub fn needs_redraw(&self) -> bool {
+        self.dirty
+            || matches!(self.view, View::Page(_))
+                && self
+                    .drawn_at
+                    .map_or(true, |at| at.elapsed() >= PAGE_REFRESH)
+    }
+
+    /// Draw the whole menu, or clear the display once it was closed.
+    pub fn draw<D: DrawTarget<Color = Rgb565>>(
+        &mut self,
+        display: &mut D,
+        settings: &Settings,
+        info: &PageInfo,
+    ) -> Result<(), D::Error> {
+        self.dirty = false;
+        self.drawn_at = Some(Instant::now());
+        display.clear(Rgb565::BLACK)?;
+
+        match self.view {
+            View::Closed => (),
+            View::List => {
+                let first = (self.selected + 1).saturating_sub(VISIBLE_ROWS);
+                for (row, (index, item)) in ITEMS
+                    .iter()
+                    .enumerate()
+                    .skip(first)
+                    .take(VISIBLE_ROWS)
+                    .enumerate()
+                {
+                    let value = item.value(settings).map(|v| v.to_string());
+                    let text = format!("{:<11}{:>12}", item.label(), value.unwrap_or_default());
+                    let y = row as i32 * ROW_HEIGHT + 2;
+                    let (color, background) = match index == self.selected {
+                        true => (Rgb565::BLACK, Rgb565::WHITE),
+                        false => (Rgb565::WHITE, Rgb565::BLACK),
+                    };
+                    Rectangle::new(Point::new(0, y), Size::new(WIDTH, ROW_HEIGHT as u32))
+                        .into_styled(PrimitiveStyle::with_fill(background))
+                        .draw(display)?;
+                    draw_line(display, &text, Point::new(2, y + 1), color)?;
+                }
+            }
+            View::Edit(item, value) => {
+                draw_line(display, item.label(), Point::new(10, 10), Rgb565::WHITE)?;
+                draw_line(
+                    display,
+                    &format!("< {value} >"),
+                    Point::new(10, 55),
+                    Rgb565::YELLOW,
+                )?;
+                draw_hint(display, "D1 -   D2 +   D0 save")?;
+            }
+            View::Page(item) => {
+                let lines = match item {
+                    Item::Network => network_page(info),
+                    _ => status_page(info),
+                };
+                for (row, line) in lines.iter().enumerate() {
+                    let at = Point::new(2, row as i32 * ROW_HEIGHT + 2);
+                    draw_line(display, line, at, Rgb565::WHITE)?;
+                }
+                draw_hint(display, "D0 back")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn status_page(info: &PageInfo) -> Vec<String> {
+    let status = &info.status;
+    let [major, minor, patch] = status.version;
+    vec![
+        format!("Firmware {major}.{minor}.{patch}"),
+        format!("Slot {}", status.running_slot),
+        format!("Boot {} {:?}", status.boot_count, status.reset_reason),
+        match &info.recording {
+            Some(name) => format!("Rec {name}"),
+            None => "Not recording".to_string(),
+        },
+        format!(
+            "Sensor {} Haptic {}",
+            ok(status.heart_ok),
+            ok(status.haptic_ok)
+        ),
+    ]
+}
+
+fn network_page(info: &PageInfo) -> Vec<String> {
+    let mac = info.mac.map(|b| format!("{b:02X}")).join(":");
+    vec![
+        match info.ip {
+            Some(ip) => format!("IP {ip}"),
+            None => "Unconnected".to_string(),
+        },
+        mac,
+        format!("Subscribers {}", info.subscribers),
+    ]
+}
+
+fn ok(ok: bool) -> &'static str {
+    match ok {
+        true => "OK",
+        false => "--",
+    }
+}
+
+fn draw_line<D: DrawTarget<Color = Rgb565>>(
+    display: &mut D,
+    text: &str,
+    at: Point,
+    color: Rgb565,
+) -> Result<(), D::Error> {
+    let style = MonoTextStyle::new(&ascii::FONT_10X20, color);
+    Text::with_baseline(text, at, style, Baseline::Top).draw(display)?;
+    Ok(())
+}
+
+fn draw_hint<D: DrawTarget<Color = Rgb565>>(display: &mut D, text: &str) -> Result<(), D::Error> {
+    let style = MonoTextStyle::new(&ascii::FONT_6X10, Rgb565::CSS_DARK_GRAY);
+    Text::with_baseline(text, Point::new(2, 123), style, Baseline::Top).draw(display)?;
+    Ok(())
+}
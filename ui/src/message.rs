@@ -0,0 +1,25 @@
+//! Full screen messages, such as the factory reset countdown.
+
+use embedded_graphics::{
+    mono_font::{ascii, MonoTextStyle},
+    pixelcolor::Rgb565,
+    prelude::*,
+    text::{Alignment, LineHeight, Text, TextStyleBuilder},
+};
+
+/// Fill the screen with `background` and write `text` on it in large letters, one
+/// centered line per `\n`.
+pub fn draw_message<D: DrawTarget<Color = Rgb565>>(
+    display: &mut D,
+    background: Rgb565,
+    text: &str,
+) -> Result<(), D::Error> {
+    display.clear(background)?;
+    let character_style = MonoTextStyle::new(&ascii::FONT_10X20, Rgb565::WHITE);
+    let text_style = TextStyleBuilder::new()
+        .alignment(Alignment::Center)
+        .line_height(LineHeight::Percent(100))
+        .build();
+    Text::with_text_style(text, Point::new(100, 50), character_style, text_style).draw(display)?;
+    Ok(())
+}
@@ -0,0 +1,178 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+
+use esp_pulser_ui::*;
+
+/// A pin whose level the test sets.
+#[derive(Clone, Default)]
+struct MockPin(Arc<AtomicBool>);
+
+impl MockPin {
+    fn set(&self, pressed: bool) {
+        self.0.store(pressed, Ordering::Relaxed);
+    }
+}
+
+impl ButtonPin for MockPin {
+    fn is_pressed(&mut self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Level changes of one pin, in milliseconds from the start.
+type Script = &'static [(u64, bool)];
+
+/// Poll the buttons every 10 ms for `duration_ms`, playing the scripts, and return the
+/// events with the time they were sent at.
+fn play(scripts: &[(Button, Script)], duration_ms: u64) -> Vec<(u64, Button, Gesture)> {
+    let (tx, rx) = mpsc::channel();
+    let pins: Vec<_> = scripts
+        .iter()
+        .map(|(button, script)| (*button, MockPin::default(), *script))
+        .collect();
+    let mut input = pins.iter().fold(
+        ButtonInput::new(GestureConfig::default(), tx),
+        |input, (button, pin, _)| input.with(*button, pin.clone()),
+    );
+
+    let start = Instant::now();
+    let mut events = Vec::new();
+    for ms in (0..=duration_ms).step_by(10) {
+        for (_, pin, script) in &pins {
+            if let Some((_, level)) = script.iter().rev().find(|(at, _)| *at <= ms) {
+                pin.set(*level);
+            }
+        }
+        assert!(input.poll(start + Duration::from_millis(ms)));
+        events.extend(rx.try_iter().map(|e| (ms, e.button, e.gesture)));
+    }
+    events
+}
+
+fn gestures(script: Script, duration_ms: u64) -> Vec<Gesture> {
+    play(&[(Button::D1, script)], duration_ms)
+        .into_iter()
+        .map(|(_, _, gesture)| gesture)
+        .collect()
+}
+
+#[test]
+fn click_waits_for_double_click_window() {
+    let events = play(&[(Button::D1, &[(100, true), (200, false)])], 1000);
+    // Released at 200, confirmed after the debounce, clicked 300 ms later
+    assert_eq!(events, vec![(500, Button::D1, Gesture::Click)]);
+}
+
+#[test]
+fn double_click() {
+    let script = &[(100, true), (180, false), (300, true), (380, false)];
+    assert_eq!(gestures(script, 1500), vec![Gesture::DoubleClick]);
+
+    // Too far apart for a double click
+    let script = &[(100, true), (180, false), (600, true), (680, false)];
+    assert_eq!(gestures(script, 1500), vec![Gesture::Click, Gesture::Click]);
+}
+
+#[test]
+fn bounces_are_ignored() {
+    let script = &[
+        (100, true),
+        (110, false),
+        (120, true),
+        (130, false),
+        (140, true),
+        (250, false),
+        (260, true),
+        (270, false),
+    ];
+    assert_eq!(gestures(script, 1000), vec![Gesture::Click]);
+
+    // Spikes shorter than the debounce time never count
+    let script = &[(100, true), (110, false), (300, true), (310, false)];
+    assert!(gestures(script, 1000).is_empty());
+}
+
+#[test]
+fn long_press_and_hold() {
+    let events = play(&[(Button::D2, &[(100, true), (1500, false)])], 2500);
+    let expected = vec![
+        (900, Button::D2, Gesture::LongPress),
+        (1100, Button::D2, Gesture::Hold(Duration::from_millis(1000))),
+        (1300, Button::D2, Gesture::Hold(Duration::from_millis(1200))),
+        (1500, Button::D2, Gesture::Hold(Duration::from_millis(1400))),
+    ];
+    // Releasing a long press is no click
+    assert_eq!(events, expected);
+}
+
+#[test]
+fn click_before_long_press_is_kept() {
+    let script = &[(100, true), (180, false), (300, true), (1200, false)];
+    assert_eq!(
+        gestures(script, 2000),
+        vec![Gesture::Click, Gesture::LongPress]
+    );
+}
+
+#[test]
+fn held_from_start() {
+    let (tx, rx) = mpsc::channel();
+    let pin = MockPin::default();
+    pin.set(true);
+    let mut input = ButtonInput::new(GestureConfig::default(), tx).with(Button::D2, pin.clone());
+
+    let start = Instant::now();
+    assert!(input.poll(start));
+    assert!(input.is_pressed(Button::D2));
+    assert!(!input.is_pressed(Button::D0));
+
+    assert!(input.poll(start + Duration::from_millis(800)));
+    let events: Vec<_> = rx.try_iter().collect();
+    assert_eq!(
+        events,
+        vec![ButtonEvent {
+            button: Button::D2,
+            gesture: Gesture::LongPress
+        }]
+    );
+
+    pin.set(false);
+    assert!(input.poll(start + Duration::from_millis(810)));
+    assert!(input.is_pressed(Button::D2), "not debounced yet");
+    assert!(input.poll(start + Duration::from_millis(830)));
+    assert!(!input.is_pressed(Button::D2));
+}
+
+#[test]
+fn buttons_are_independent() {
+    let events = play(
+        &[
+            (Button::D0, &[(100, true), (200, false)]),
+            (
+                Button::D1,
+                &[(150, true), (250, false), (350, true), (450, false)],
+            ),
+        ],
+        1000,
+    );
+    assert_eq!(
+        events,
+        vec![
+            (470, Button::D1, Gesture::DoubleClick),
+            (500, Button::D0, Gesture::Click),
+        ]
+    );
+}
+
+#[test]
+fn stops_without_receiver() {
+    let (tx, rx) = mpsc::channel();
+    let pin = MockPin::default();
+    let mut input = ButtonInput::new(GestureConfig::default(), tx).with(Button::D0, pin.clone());
+    let start = Instant::now();
+    pin.set(true);
+    assert!(input.poll(start));
+    drop(rx);
+    assert!(!input.poll(start + Duration::from_millis(800)));
+}
@@ -0,0 +1,261 @@
+use std::convert::Infallible;
+use std::fs;
+use std::net::Ipv4Addr;
+use std::path::Path;
+
+use embedded_graphics::{pixelcolor::Rgb565, prelude::*};
+use esp_pulser_protocol::{ResetReason, Settings, Status};
+use esp_pulser_ui::*;
+
+/// Every color the screens use, with the character it is stored as in a snapshot.
+const PALETTE: [(Rgb565, char); 9] = [
+    (Rgb565::BLACK, '.'),
+    (Rgb565::WHITE, '#'),
+    (Rgb565::RED, 'R'),
+    (Rgb565::GREEN, 'G'),
+    (Rgb565::BLUE, 'B'),
+    (Rgb565::YELLOW, 'Y'),
+    (Rgb565::CSS_DIM_GRAY, 'd'),
+    (Rgb565::CSS_DARK_GRAY, 'k'),
+    (Rgb565::CSS_DARK_SLATE_GRAY, 's'),
+];
+
+/// The display in memory. Drawing outside of it fails the test, as the ST7789 would
+/// wrap around instead of clipping.
+#[derive(Clone, PartialEq)]
+struct Framebuffer {
+    pixels: Vec<Rgb565>,
+}
+
+impl Framebuffer {
+    /// A framebuffer filled with a color no screen uses, so anything left undrawn
+    /// shows up in the snapshot.
+    fn new() -> Self {
+        Self {
+            pixels: vec![Rgb565::MAGENTA; (WIDTH * HEIGHT) as usize],
+        }
+    }
+
+    fn to_text(&self) -> String {
+        let mut text = String::new();
+        for row in self.pixels.chunks(WIDTH as usize) {
+            for color in row {
+                let c = PALETTE
+                    .iter()
+                    .find(|(c, _)| c == color)
+                    .map_or('?', |(_, c)| *c);
+                text.push(c);
+            }
+            text.push('\n');
+        }
+        text
+    }
+}
+
+impl OriginDimensions for Framebuffer {
+    fn size(&self) -> Size {
+        Size::new(WIDTH, HEIGHT)
+    }
+}
+
+impl DrawTarget for Framebuffer {
+    type Color = Rgb565;
+    type Error = Infallible;
+
+    fn draw_iter<I: IntoIterator<Item = Pixel<Rgb565>>>(
+        &mut self,
+        pixels: I,
+    ) -> Result<(), Infallible> {
+        for Pixel(point, color) in pixels {
+            assert!(
+                self.bounding_box().contains(point),
+                "drawn outside of the display at {point:?}"
+            );
+            self.pixels[(point.y as u32 * WIDTH + point.x as u32) as usize] = color;
+        }
+        Ok(())
+    }
+}
+
+/// Compare the framebuffer with `tests/snapshots/<name>.txt`. Run the tests with
+/// `UPDATE_SNAPSHOTS=1` to write the snapshots instead, after checking the new
+/// rendering, which is left in the target directory on a mismatch.
+fn assert_snapshot(name: &str, framebuffer: &Framebuffer) {
+    let actual = framebuffer.to_text();
+    assert!(
+        !actual.contains('?'),
+        "{name}: color missing from the palette"
+    );
+
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(format!("tests/snapshots/{name}.txt"));
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::write(&path, &actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("{name}: {e}, run with UPDATE_SNAPSHOTS=1 to create it"));
+    if actual != expected {
+        let rendered = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{name}.txt"));
+        fs::write(&rendered, &actual).unwrap();
+        let row = actual
+            .lines()
+            .zip(expected.lines())
+            .position(|(a, e)| a != e)
+            .unwrap_or_default();
+        panic!(
+            "{name}: rendering differs from the snapshot from row {row}, see {}",
+            rendered.display()
+        );
+    }
+}
+
+/// A pulse-like waveform, a sharp peak followed by a smaller bump.
+fn waveform(len: usize) -> Vec<f32> {
+    (0..len)
+        .map(|i| {
+            let phase = (i % 50) as f32 / 50.0;
+            let peak = (-((phase - 0.2) * 12.0).powi(2)).exp() * 1000.0;
+            let notch = (-((phase - 0.5) * 10.0).powi(2)).exp() * 300.0;
+            peak + notch
+        })
+        .collect()
+}
+
+fn frame(points: Vec<f32>, bpm: f32, finger: bool, ip: Option<Ipv4Addr>) -> Frame {
+    Frame {
+        points,
+        bpm,
+        beat: false,
+        finger,
+        ip,
+    }
+}
+
+fn draw_live(frames: &[Frame]) -> Framebuffer {
+    let mut display = Framebuffer::new();
+    let mut screen = LiveScreen::default();
+    for frame in frames {
+        screen.draw(&mut display, frame).unwrap();
+    }
+    display
+}
+
+const IP: Option<Ipv4Addr> = Some(Ipv4Addr::new(192, 168, 1, 50));
+
+#[test]
+fn live_screen() {
+    // More than one sweep, so the scale is fitted and the cursor wraps around
+    let mut frames: Vec<_> = waveform(300)
+        .chunks(3)
+        .map(|points| frame(points.to_vec(), 72.0, true, IP))
+        .collect();
+    frames.last_mut().unwrap().beat = true;
+    assert_snapshot("live", &draw_live(&frames));
+}
+
+#[test]
+fn live_screen_waiting() {
+    let display = draw_live(&[frame(Vec::new(), 0.0, false, None)]);
+    assert_snapshot("live_waiting", &display);
+}
+
+#[test]
+fn live_screen_skips_non_finite_points() {
+    let points = waveform(60);
+    let mut broken = vec![f32::NAN];
+    broken.extend(&points[..30]);
+    broken.extend([f32::INFINITY, f32::NEG_INFINITY, f32::NAN]);
+    broken.extend(&points[30..]);
+
+    let display = draw_live(&[frame(broken, 72.0, true, IP)]);
+    let clean = draw_live(&[frame(points, 72.0, true, IP)]);
+    assert!(display == clean, "non-finite points drawn");
+}
+
+#[test]
+fn live_screen_redraws_changes() {
+    let before = frame(waveform(10), 123.0, true, IP);
+    let after = frame(Vec::new(), 0.0, false, None);
+    let updated = draw_live(&[before, after.clone()]);
+    let fresh = draw_live(&[after]);
+    // Header and separator line
+    let header = (WIDTH * 33) as usize;
+    assert!(
+        updated.pixels[..header] == fresh.pixels[..header],
+        "header not redrawn in place"
+    );
+}
+
+fn page_info() -> PageInfo {
+    let mut status = Status::new([0, 7, 0]);
+    status.running_slot = "ota_1".to_string();
+    status.reset_reason = ResetReason::PowerOn;
+    status.boot_count = 42;
+    status.heart_ok = true;
+    status.haptic_ok = false;
+    PageInfo {
+        status,
+        ip: IP,
+        mac: [0x98, 0x76, 0xB6, 0x12, 0xF9, 0x93],
+        subscribers: 2,
+        recording: Some("rec_0003.bin".to_string()),
+    }
+}
+
+/// Open the menu and press the buttons, then draw it.
+fn draw_menu(presses: &[Button]) -> Framebuffer {
+    let settings = Settings::default();
+    let mut menu = Menu::default();
+    assert_eq!(menu.press(Button::D0, &settings), None);
+    for button in presses {
+        menu.press(*button, &settings);
+    }
+    assert!(menu.needs_redraw());
+    let mut display = Framebuffer::new();
+    menu.draw(&mut display, &settings, &page_info()).unwrap();
+    display
+}
+
+#[test]
+fn menu_list() {
+    assert_snapshot("menu_list", &draw_menu(&[]));
+}
+
+#[test]
+fn menu_list_scrolled() {
+    let display = draw_menu(&[Button::D2; 7]);
+    assert_snapshot("menu_list_scrolled", &display);
+}
+
+#[test]
+fn menu_edit() {
+    let display = draw_menu(&[Button::D2, Button::D2, Button::D0, Button::D1]);
+    assert_snapshot("menu_edit", &display);
+}
+
+#[test]
+fn menu_status_page() {
+    assert_snapshot("menu_status", &draw_menu(&[Button::D0]));
+}
+
+#[test]
+fn menu_network_page() {
+    let display = draw_menu(&[Button::D2, Button::D0]);
+    assert_snapshot("menu_network", &display);
+}
+
+#[test]
+fn closed_menu_clears_display() {
+    let display = draw_menu(&[Button::D1, Button::D0]);
+    assert!(display.pixels.iter().all(|c| *c == Rgb565::BLACK));
+}
+
+#[test]
+fn factory_reset_messages() {
+    let mut display = Framebuffer::new();
+    draw_message(&mut display, Rgb565::BLUE, "Hold D2 to reset\n3").unwrap();
+    assert_snapshot("factory_reset_countdown", &display);
+
+    draw_message(&mut display, Rgb565::GREEN, "Factory reset\ndone").unwrap();
+    assert_snapshot("factory_reset_done", &display);
+}